@@ -0,0 +1,46 @@
+//! Inline headers against the side table, run with `cargo +nightly bench`
+#![feature(test)]
+
+extern crate test;
+
+use core::alloc::{GlobalAlloc, Layout};
+
+use test::{Bencher, black_box};
+use yerba::{linked_list_allocator::LinkedListAllocator, side_table_allocator::SideTableAllocator};
+
+const COUNT: usize = 256;
+
+/// Allocates a batch of small blocks, sums their data, then frees them
+fn churn<A: GlobalAlloc>(allocator: &A, b: &mut Bencher) {
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    let mut ptrs = [core::ptr::null_mut::<u8>(); COUNT];
+
+    b.iter(|| unsafe {
+        for (i, ptr) in ptrs.iter_mut().enumerate() {
+            *ptr = allocator.alloc(layout);
+            ptr.write_bytes(i as u8, layout.size());
+        }
+        let sum = ptrs
+            .iter()
+            .map(|ptr| {
+                (0..layout.size())
+                    .map(|i| ptr.add(i).read() as usize)
+                    .sum::<usize>()
+            })
+            .sum::<usize>();
+        black_box(sum);
+        for ptr in ptrs {
+            allocator.dealloc(ptr, layout);
+        }
+    });
+}
+
+#[bench]
+fn inline_headers(b: &mut Bencher) {
+    churn(&LinkedListAllocator::new(), b);
+}
+
+#[bench]
+fn side_table(b: &mut Bencher) {
+    churn(&SideTableAllocator::new(), b);
+}
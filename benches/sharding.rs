@@ -0,0 +1,44 @@
+//! One locked arena against one per CPU, under several threads, run with `cargo +nightly bench`
+#![feature(test)]
+
+extern crate test;
+
+use core::alloc::{GlobalAlloc, Layout};
+use std::thread;
+
+use test::Bencher;
+use yerba::sharded_allocator::ShardedAllocator;
+
+const THREADS: usize = 4;
+const COUNT: usize = 64;
+
+/// Every thread allocates and frees a batch of small blocks, over and over
+fn contend<const SHARDS: usize>(b: &mut Bencher) {
+    let allocator = ShardedAllocator::<SHARDS>::new();
+    let layout = Layout::new::<[u8; 24]>();
+
+    b.iter(|| {
+        thread::scope(|scope| {
+            for _ in 0..THREADS {
+                scope.spawn(|| unsafe {
+                    for _ in 0..16 {
+                        let ptrs = [(); COUNT].map(|_| allocator.alloc(layout));
+                        for ptr in ptrs {
+                            allocator.dealloc(ptr, layout);
+                        }
+                    }
+                });
+            }
+        });
+    });
+}
+
+#[bench]
+fn single_lock(b: &mut Bencher) {
+    contend::<1>(b);
+}
+
+#[bench]
+fn sharded(b: &mut Bencher) {
+    contend::<THREADS>(b);
+}
@@ -0,0 +1,476 @@
+use core::alloc::{AllocError, Allocator, GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+
+use crate::const_arena::ConstArena;
+use crate::error::AllocFailure;
+use crate::hierarchical_allocator::HierarchicalAllocator;
+use crate::histogram::Histogram;
+use crate::linear_allocator::{FrameAllocator, GrowableLinear, LinearAllocator, TypedArena};
+use crate::linked_list_allocator::LinkedListAllocator;
+use crate::page_allocator::{YerbaAlloc, header_of, header_offset};
+use crate::pool_allocator::PoolAllocator;
+use crate::ring_allocator::RingAllocator;
+use crate::sharded_allocator::ShardedAllocator;
+use crate::side_table_allocator::SideTableAllocator;
+use crate::stack_allocator::{GrowableStack, StackAllocator};
+
+fn dangling(layout: Layout) -> NonNull<[u8]> {
+    NonNull::slice_from_raw_parts(layout.dangling_ptr(), 0)
+}
+
+unsafe impl Allocator for YerbaAlloc {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        allocate_with(self, layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            unsafe { self.dealloc(ptr.as_ptr(), layout) };
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if old_layout.size() == 0 {
+            return self.allocate(new_layout);
+        }
+
+        // Rounded up to a power of two, so a caller growing by a little at a time maps O(log n) times
+        let rounded = new_layout
+            .size()
+            .checked_next_power_of_two()
+            .and_then(|size| Layout::from_size_align(size, new_layout.align()).ok());
+        let mut new_ptr = ptr::null_mut();
+        if let Some(rounded) = rounded {
+            new_ptr = unsafe { self.realloc_aligned(ptr.as_ptr(), old_layout, rounded) };
+        }
+        if new_ptr.is_null() {
+            new_ptr = unsafe { self.realloc_aligned(ptr.as_ptr(), old_layout, new_layout) };
+        }
+        if new_ptr.is_null() {
+            return Err(AllocError);
+        }
+
+        // The whole mapping is usable, so it's reported as the capacity
+        // The block may then be released with any size from the requested one up to it
+        let usable = unsafe { (*header_of(new_ptr, new_layout.align())).len };
+        let usable = usable - header_offset(new_layout.align());
+        self.layouts
+            .set_capacity(new_ptr, new_layout.size(), usable);
+
+        Ok(NonNull::slice_from_raw_parts(
+            unsafe { NonNull::new_unchecked(new_ptr) },
+            usable,
+        ))
+    }
+
+    /// Unmaps the pages that are no longer covered by `new_layout`
+    /// The allocation only moves if the new alignment puts the data elsewhere in the mapping
+    /// Otherwise it stays put, and if it still spans the same pages no syscall is made
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if old_layout.size() == 0 {
+            return Ok(dangling(new_layout));
+        }
+        if new_layout.size() == 0 {
+            unsafe { self.dealloc(ptr.as_ptr(), old_layout) };
+            return Ok(dangling(new_layout));
+        }
+        if header_offset(new_layout.align()) != header_offset(old_layout.align()) {
+            let new_ptr = unsafe { self.realloc_aligned(ptr.as_ptr(), old_layout, new_layout) };
+            return NonNull::new(new_ptr)
+                .map(|ptr| NonNull::slice_from_raw_parts(ptr, new_layout.size()))
+                .ok_or(AllocError);
+        }
+
+        unsafe { self.unmap_tail(ptr.as_ptr(), old_layout.align(), new_layout.size()) };
+        self.layouts
+            .resize(ptr.as_ptr(), old_layout, ptr.as_ptr(), new_layout.size());
+        self.layouts.set_align(ptr.as_ptr(), new_layout.align());
+
+        Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size()))
+    }
+}
+
+/// Allocates with `alloc`, reporting null as an error
+fn allocate_with<A: GlobalAlloc>(
+    allocator: &A,
+    layout: Layout,
+) -> Result<NonNull<[u8]>, AllocError> {
+    if layout.size() == 0 {
+        return Ok(dangling(layout));
+    }
+
+    let ptr = unsafe { allocator.alloc(layout) };
+    NonNull::new(ptr)
+        .map(|ptr| NonNull::slice_from_raw_parts(ptr, layout.size()))
+        .ok_or(AllocError)
+}
+
+/// Resizes with `realloc`, which can't raise the alignment of a block
+unsafe fn resize_with<A: GlobalAlloc>(
+    allocator: &A,
+    ptr: NonNull<u8>,
+    old_layout: Layout,
+    new_layout: Layout,
+) -> Result<NonNull<[u8]>, AllocError> {
+    if old_layout.size() == 0 {
+        return allocate_with(allocator, new_layout);
+    }
+    if new_layout.align() > old_layout.align() {
+        return Err(AllocError);
+    }
+    if new_layout.size() == 0 {
+        unsafe { allocator.dealloc(ptr.as_ptr(), old_layout) };
+        return Ok(dangling(new_layout));
+    }
+
+    let new_ptr = unsafe { allocator.realloc(ptr.as_ptr(), old_layout, new_layout.size()) };
+    NonNull::new(new_ptr)
+        .map(|ptr| NonNull::slice_from_raw_parts(ptr, new_layout.size()))
+        .ok_or(AllocError)
+}
+
+/// Blocks are freed in LIFO order like the `GlobalAlloc` impl, and only the top block can be resized
+unsafe impl Allocator for StackAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        allocate_with(self, layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            unsafe { self.dealloc(ptr.as_ptr(), layout) };
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { resize_with(self, ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { resize_with(self, ptr, old_layout, new_layout) }
+    }
+}
+
+/// `dealloc` releases the whole arena, which would pull memory out from under every other live block
+/// so `deallocate` leaves blocks reserved until `LinearAllocator::reset`
+unsafe impl Allocator for LinearAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        allocate_with(self, layout)
+    }
+
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {}
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { resize_with(self, ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if new_layout.size() == 0 {
+            return Ok(dangling(new_layout));
+        }
+        unsafe { resize_with(self, ptr, old_layout, new_layout) }
+    }
+}
+
+/// Allocators that can be set up without arguments, so generic code can create any of them the same way
+pub trait ConstructibleAllocator: Sized {
+    /// Creates the allocator, reporting a failure to get its memory from the OS instead of panicking
+    fn try_new() -> Result<Self, AllocFailure>;
+}
+
+/// Implements `ConstructibleAllocator` for allocators whose `new` can't fail
+macro_rules! infallible_new {
+    ($($allocator:ty),*) => {
+        $(impl ConstructibleAllocator for $allocator {
+            fn try_new() -> Result<Self, AllocFailure> {
+                Ok(Self::new())
+            }
+        })*
+    };
+}
+
+infallible_new!(
+    StackAllocator,
+    GrowableStack,
+    LinearAllocator,
+    FrameAllocator,
+    RingAllocator,
+    YerbaAlloc,
+    HierarchicalAllocator
+);
+
+impl<T> ConstructibleAllocator for TypedArena<T> {
+    fn try_new() -> Result<Self, AllocFailure> {
+        Ok(Self::new())
+    }
+}
+
+impl<const N: usize> ConstructibleAllocator for ConstArena<N> {
+    fn try_new() -> Result<Self, AllocFailure> {
+        Ok(Self::new())
+    }
+}
+
+impl<const SIZE: usize, const COUNT: usize> ConstructibleAllocator for PoolAllocator<SIZE, COUNT> {
+    fn try_new() -> Result<Self, AllocFailure> {
+        Ok(Self::new())
+    }
+}
+
+impl ConstructibleAllocator for LinkedListAllocator {
+    fn try_new() -> Result<Self, AllocFailure> {
+        LinkedListAllocator::try_new()
+    }
+}
+
+impl ConstructibleAllocator for GrowableLinear {
+    fn try_new() -> Result<Self, AllocFailure> {
+        GrowableLinear::try_new()
+    }
+}
+
+impl ConstructibleAllocator for SideTableAllocator {
+    fn try_new() -> Result<Self, AllocFailure> {
+        SideTableAllocator::try_new()
+    }
+}
+
+impl<const SHARDS: usize> ConstructibleAllocator for ShardedAllocator<SHARDS> {
+    fn try_new() -> Result<Self, AllocFailure> {
+        ShardedAllocator::try_new()
+    }
+}
+
+/// Wraps a freshly constructed inner allocator
+impl<A: GlobalAlloc + ConstructibleAllocator> ConstructibleAllocator for Histogram<A> {
+    fn try_new() -> Result<Self, AllocFailure> {
+        Ok(Histogram::new(A::try_new()?))
+    }
+}
+
+/// A safe `alloc` for every allocator, returning None instead of a null pointer
+pub trait AllocNonNull: GlobalAlloc {
+    /// Allocates a block for `layout`, None if the allocator can't satisfy it
+    /// Zero sized layouts don't touch the allocator and get a dangling pointer aligned for `layout`
+    /// Free the block with `dealloc` as usual, apart from zero sized ones which need no freeing
+    fn alloc_nonnull(&self, layout: Layout) -> Option<NonNull<u8>> {
+        if layout.size() == 0 {
+            return Some(layout.dangling_ptr());
+        }
+
+        NonNull::new(unsafe { self.alloc(layout) })
+    }
+}
+
+impl<A: GlobalAlloc + ?Sized> AllocNonNull for A {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::page_allocator::PAGE_SIZE;
+
+    #[test]
+    fn grow_then_shrink() {
+        let allocator = YerbaAlloc::new();
+        let page = *PAGE_SIZE;
+        let small = Layout::from_size_align(page / 2, 8).unwrap();
+        let large = Layout::from_size_align(page * 3 + page / 2, 8).unwrap();
+        let same_pages = Layout::from_size_align(page * 3 + 1, 8).unwrap();
+        let shrunk = Layout::from_size_align(page + 1, 8).unwrap();
+
+        unsafe {
+            let ptr = allocator.allocate(small).unwrap().cast::<u8>();
+            (0..small.size()).for_each(|i| ptr.add(i).write(i as u8));
+
+            let grown = allocator.grow(ptr, small, large).unwrap();
+            assert!(grown.len() >= large.size());
+            let ptr = grown.cast::<u8>();
+            (0..small.size()).for_each(|i| assert_eq!(ptr.add(i).read(), i as u8));
+            (0..large.size()).for_each(|i| ptr.add(i).write(i as u8));
+
+            let same = allocator.shrink(ptr, large, same_pages).unwrap();
+            assert_eq!(same.cast::<u8>(), ptr);
+            assert_eq!(same.len(), same_pages.size());
+
+            let shrunk_ptr = allocator.shrink(ptr, same_pages, shrunk).unwrap();
+            assert_eq!(shrunk_ptr.cast::<u8>(), ptr);
+            assert_eq!(shrunk_ptr.len(), shrunk.size());
+            (0..shrunk.size()).for_each(|i| assert_eq!(ptr.add(i).read(), i as u8));
+
+            allocator.deallocate(ptr, shrunk);
+        }
+    }
+
+    #[test]
+    fn amortized_growth() {
+        let allocator = YerbaAlloc::new();
+        let count = 10_000;
+
+        let mut vec: Vec<u64, _> = Vec::new_in(&allocator);
+        for i in 0..count {
+            // Asks for exactly one more element each time, the allocator rounds up
+            vec.reserve_exact(1);
+            vec.push(i);
+        }
+        assert!(vec.iter().copied().eq(0..count));
+
+        let stats = allocator.stats();
+        let resizes = stats.maps + stats.remaps;
+        assert!(resizes <= count.ilog2() as usize, "resized {resizes} times");
+    }
+
+    #[test]
+    fn alloc_nonnull() {
+        let allocator = LinearAllocator::new();
+
+        let layout = Layout::new::<[u64; 4]>();
+        let ptr = allocator.alloc_nonnull(layout).unwrap();
+        assert!(ptr.as_ptr().addr().is_multiple_of(layout.align()));
+        assert!(allocator.as_ptr_range().contains(&ptr.as_ptr()));
+
+        let zst = Layout::from_size_align(0, 64).unwrap();
+        let dangling = allocator.alloc_nonnull(zst).unwrap();
+        assert_eq!(dangling.as_ptr().addr(), 64);
+
+        assert!(
+            allocator
+                .alloc_nonnull(Layout::new::<[u8; 8192]>())
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn grow_alignment() {
+        let allocator = YerbaAlloc::new();
+        let small = Layout::from_size_align(64, 16).unwrap();
+        let align = *PAGE_SIZE * 4;
+        let large = Layout::from_size_align(*PAGE_SIZE * 2, align).unwrap();
+
+        unsafe {
+            let ptr = allocator.allocate(small).unwrap().cast::<u8>();
+            (0..small.size()).for_each(|i| ptr.add(i).write(i as u8));
+
+            let grown = allocator.grow(ptr, small, large).unwrap().cast::<u8>();
+            assert!(grown.as_ptr().addr().is_multiple_of(align));
+            (0..small.size()).for_each(|i| assert_eq!(grown.add(i).read(), i as u8));
+            grown.add(large.size() - 1).write(1);
+
+            let shrunk = allocator.shrink(grown, large, small).unwrap().cast::<u8>();
+            (0..small.size()).for_each(|i| assert_eq!(shrunk.add(i).read(), i as u8));
+            allocator.deallocate(shrunk, small);
+        }
+
+        let stats = allocator.stats();
+        assert_eq!(stats.maps, 3);
+        assert_eq!(stats.bytes_mapped, stats.bytes_unmapped);
+    }
+
+    #[test]
+    fn resize_same_offset_alignment() {
+        let allocator = YerbaAlloc::new();
+        let small = Layout::from_size_align(16, 1).unwrap();
+        let large = Layout::from_size_align(32, 8).unwrap();
+
+        // Both alignments share a header offset, so the block is resized in place
+        // Freeing with the new layout must match what's recorded
+        unsafe {
+            let ptr = allocator.allocate(small).unwrap().cast::<u8>();
+            let grown = allocator.grow(ptr, small, large).unwrap().cast::<u8>();
+            allocator.deallocate(grown, large);
+
+            let ptr = allocator.allocate(large).unwrap().cast::<u8>();
+            let shrunk = allocator.shrink(ptr, large, small).unwrap().cast::<u8>();
+            allocator.deallocate(shrunk, small);
+        }
+    }
+
+    #[test]
+    fn stack_vec() {
+        let allocator = StackAllocator::new();
+
+        let mut vec: Vec<u64, _> = Vec::with_capacity_in(8, &allocator);
+        (0..64).for_each(|i| vec.push(i));
+        assert!(vec.iter().copied().eq(0..64));
+        assert!(
+            allocator
+                .as_ptr_range()
+                .contains(&vec.as_mut_ptr().cast::<u8>())
+        );
+
+        vec.truncate(4);
+        vec.shrink_to_fit();
+        assert_eq!(vec, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn linear_vec() {
+        let allocator = LinearAllocator::new();
+
+        let mut one: Vec<u64, _> = Vec::with_capacity_in(8, &allocator);
+        (0..8).for_each(|i| one.push(i));
+        let mut two: Vec<u64, _> = Vec::with_capacity_in(8, &allocator);
+        (0..64).for_each(|i| two.push(i));
+        drop(two);
+
+        // Dropping one collection leaves the others intact
+        one.push(8);
+        assert!(one.iter().copied().eq(0..9));
+        assert!(
+            allocator
+                .as_ptr_range()
+                .contains(&one.as_mut_ptr().cast::<u8>())
+        );
+    }
+
+    #[test]
+    fn constructible() {
+        fn construct<A: ConstructibleAllocator>() -> A {
+            A::try_new().unwrap()
+        }
+
+        construct::<StackAllocator>();
+        construct::<GrowableStack>();
+        construct::<LinearAllocator>();
+        construct::<FrameAllocator>();
+        construct::<TypedArena<u64>>();
+        construct::<ConstArena<64>>();
+        construct::<RingAllocator>();
+        construct::<PoolAllocator<64, 4>>();
+        construct::<YerbaAlloc>();
+        construct::<Histogram<YerbaAlloc>>();
+        construct::<HierarchicalAllocator>();
+        construct::<LinkedListAllocator>().free_allocator();
+        construct::<GrowableLinear>().free_allocator();
+        construct::<SideTableAllocator>().free_allocator();
+        construct::<ShardedAllocator<2>>().free_allocator();
+    }
+}
@@ -0,0 +1,129 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+};
+
+/// Owns a `T` allocated from a borrowed allocator, like `Box` but without the nightly `Allocator` trait
+/// Dropping it drops the value and frees its block
+pub struct YerbaBox<'a, T, A: GlobalAlloc> {
+    ptr: NonNull<T>,
+    allocator: &'a A,
+    _marker: PhantomData<T>,
+}
+
+// Owns its value like a `Box`, and shares the allocator with whoever else borrows it
+unsafe impl<T: Send, A: GlobalAlloc + Sync> Send for YerbaBox<'_, T, A> {}
+unsafe impl<T: Sync, A: GlobalAlloc + Sync> Sync for YerbaBox<'_, T, A> {}
+
+impl<'a, T, A: GlobalAlloc> YerbaBox<'a, T, A> {
+    /// Moves `value` into a block from `allocator`, None if it can't be allocated
+    /// Zero sized values don't touch the allocator
+    ///
+    /// # Safety
+    /// `allocator` must keep the block valid until the box frees it, it can't be reset or lapped before then
+    pub unsafe fn new_in(value: T, allocator: &'a A) -> Option<Self> {
+        let layout = Layout::new::<T>();
+        let ptr = if layout.size() == 0 {
+            NonNull::dangling()
+        } else {
+            NonNull::new(unsafe { allocator.alloc(layout) }.cast::<T>())?
+        };
+        unsafe { ptr.write(value) };
+
+        Some(YerbaBox {
+            ptr,
+            allocator,
+            _marker: PhantomData,
+        })
+    }
+
+    /// The allocator the value lives in
+    pub fn allocator(&self) -> &'a A {
+        self.allocator
+    }
+}
+
+impl<T, A: GlobalAlloc> Deref for YerbaBox<'_, T, A> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T, A: GlobalAlloc> DerefMut for YerbaBox<'_, T, A> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T, A: GlobalAlloc> Drop for YerbaBox<'_, T, A> {
+    fn drop(&mut self) {
+        let layout = Layout::new::<T>();
+        unsafe {
+            self.ptr.drop_in_place();
+            if layout.size() != 0 {
+                self.allocator.dealloc(self.ptr.as_ptr().cast(), layout);
+            }
+        }
+    }
+}
+
+impl<T: fmt::Debug, A: GlobalAlloc> fmt::Debug for YerbaBox<'_, T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::cell::Cell;
+
+    use super::*;
+    use crate::{linear_allocator::LinearAllocator, page_allocator::YerbaAlloc};
+
+    // Sendable when the allocator can be shared, like a `Box<T>`
+    const _: () = {
+        crate::assert_send::<YerbaBox<'static, u64, YerbaAlloc>>();
+        crate::assert_sync::<YerbaBox<'static, u64, YerbaAlloc>>();
+    };
+    crate::assert_not_impl!(YerbaBox<'static, u64, LinearAllocator>, Send);
+    crate::assert_not_impl!(YerbaBox<'static, Cell<u64>, YerbaAlloc>, Sync);
+
+    #[test]
+    fn drop_frees() {
+        let allocator = YerbaAlloc::new();
+
+        let mut boxed = unsafe { YerbaBox::new_in([0u8; 32], &allocator) }.unwrap();
+        boxed[3] = 7;
+        boxed.iter_mut().skip(16).for_each(|byte| *byte = 1);
+        assert_eq!(boxed[..4], [0, 0, 0, 7]);
+        assert_eq!(boxed.iter().map(|&byte| byte as usize).sum::<usize>(), 23);
+        assert_eq!(allocator.stats().maps, 1);
+
+        drop(boxed);
+        assert_eq!(allocator.stats().unmaps, 1);
+    }
+
+    #[test]
+    fn runs_destructor() {
+        struct Counted<'a>(&'a Cell<usize>);
+
+        impl Drop for Counted<'_> {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let allocator = YerbaAlloc::new();
+        let drops = Cell::new(0);
+
+        drop(unsafe { YerbaBox::new_in(Counted(&drops), &allocator) }.unwrap());
+        drop(unsafe { YerbaBox::new_in((), &allocator) }.unwrap());
+        assert_eq!(drops.get(), 1);
+        assert_eq!(allocator.stats().maps, 1);
+    }
+}
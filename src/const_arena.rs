@@ -0,0 +1,145 @@
+use core::{
+    alloc::GlobalAlloc,
+    cell::UnsafeCell,
+    fmt,
+    ops::Range,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+#[repr(C, align(16))]
+struct Buffer<const N: usize>([u8; N]);
+
+/// Bumps allocations upwards through a buffer of `N` bytes that's built at compile time
+/// Needs no syscalls, so it can back a `static` and serve allocations before `main` or without an OS
+/// Individual blocks can't be freed
+///
+/// Can be installed as the global allocator
+/// ```no_run
+/// use yerba::const_arena::ConstArena;
+///
+/// #[global_allocator]
+/// static ARENA: ConstArena<65536> = ConstArena::new();
+///
+/// fn main() {}
+/// ```
+pub struct ConstArena<const N: usize> {
+    buf: UnsafeCell<Buffer<N>>,
+    offset: AtomicUsize,
+}
+
+// Regions are reserved by atomically moving the offset and never handed out twice
+unsafe impl<const N: usize> Sync for ConstArena<N> {}
+
+impl<const N: usize> ConstArena<N> {
+    /// Number of bytes in the arena
+    pub const CAPACITY: usize = N;
+
+    pub const fn new() -> Self {
+        ConstArena {
+            buf: UnsafeCell::new(Buffer([0; N])),
+            offset: AtomicUsize::new(0),
+        }
+    }
+
+    /// Number of bytes handed out so far, including alignment padding
+    pub fn used(&self) -> usize {
+        self.offset.load(Ordering::Relaxed)
+    }
+
+    /// The whole arena, every block this allocator hands out lies within it
+    pub fn as_ptr_range(&self) -> Range<*mut u8> {
+        let start = self.buf_ptr();
+        start..unsafe { start.add(N) }
+    }
+
+    fn buf_ptr(&self) -> *mut u8 {
+        self.buf.get().cast()
+    }
+}
+
+impl<const N: usize> Default for ConstArena<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> fmt::Debug for ConstArena<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConstArena")
+            .field("capacity", &N)
+            .field("used", &self.used())
+            .finish()
+    }
+}
+
+unsafe impl<const N: usize> GlobalAlloc for ConstArena<N> {
+    #[track_caller]
+    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
+        let size = layout.size();
+        let align = crate::checked_align(layout.align());
+
+        let mut start = 0;
+        let bumped = self
+            .offset
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |offset| {
+                let padding = crate::padding_for(self.buf_ptr().wrapping_add(offset), align);
+                start = offset.checked_add(padding)?;
+                start.checked_add(size).filter(|&end| end <= N)
+            });
+        if bumped.is_err() {
+            return crate::error::alloc_error(layout);
+        }
+
+        unsafe { self.buf_ptr().add(start) }
+    }
+
+    /// Does nothing, the arena is never freed
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: core::alloc::Layout) {}
+
+    /// Copies the block to a new one, like the default, but returns null for a size that doesn't make a layout
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: core::alloc::Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        let Some(new_layout) = crate::realloc_layout(layout, new_size) else {
+            return ptr::null_mut();
+        };
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe { ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size)) };
+        }
+
+        new_ptr
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::alloc::Layout;
+
+    use super::*;
+
+    // Blocks are placed by atomically moving the offset, so it can be a static global allocator
+    const _: () = {
+        crate::assert_send::<ConstArena<64>>();
+        crate::assert_sync::<ConstArena<64>>();
+    };
+
+    static ARENA: ConstArena<1024> = ConstArena::new();
+
+    #[test]
+    fn static_arena() {
+        unsafe {
+            let one = ARENA.alloc(Layout::new::<u8>());
+            let two = ARENA.alloc(Layout::new::<u64>());
+            assert!(ARENA.as_ptr_range().contains(&one));
+            assert!(two.addr().is_multiple_of(align_of::<u64>()));
+            assert!(two > one);
+
+            assert!(ARENA.alloc(Layout::new::<[u8; 1024]>()).is_null());
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use core::{
+    alloc::Layout,
+    fmt, ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+/// Hook registered with `set_alloc_error_hook`, a `fn(Layout)` stored as a pointer, null if there is none
+static ALLOC_ERROR_HOOK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Why an allocator couldn't get more memory from the OS
+/// Allocation paths report it by returning null rather than panicking
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocFailure {
+    /// Every page of the reserved arena is already committed
+    ArenaFull,
+    /// The OS refused to back another page
+    CommitFailed,
+    /// The OS refused to reserve the arena, or placed it where its end wraps around the address space
+    ReserveFailed,
+}
+
+impl fmt::Display for AllocFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AllocFailure::ArenaFull => "every page of the arena is already committed",
+            AllocFailure::CommitFailed => "the OS refused to back another page",
+            AllocFailure::ReserveFailed => {
+                "the arena couldn't be reserved, or its reservation wraps around the address space"
+            }
+        })
+    }
+}
+
+/// Registers `hook` to run whenever an allocator that can be installed as the global allocator fails,
+/// just before it returns null and the runtime goes on to `handle_alloc_error`
+/// Lets a `no_std` program report the failure, over a serial port say, before it aborts
+/// Covers `ConstArena`, `StackAllocator` and `YerbaAlloc`, and replaces any hook registered before
+/// The hook runs inside the failing allocation, so it must not allocate from the same allocator
+pub fn set_alloc_error_hook(hook: fn(Layout)) {
+    ALLOC_ERROR_HOOK.store(hook as *mut (), Ordering::Release);
+}
+
+/// Runs the hook registered with `set_alloc_error_hook`, if any, and returns the null the caller passes on
+pub(crate) fn alloc_error(layout: Layout) -> *mut u8 {
+    let hook = ALLOC_ERROR_HOOK.load(Ordering::Acquire);
+    if !hook.is_null() {
+        let hook = unsafe { core::mem::transmute::<*mut (), fn(Layout)>(hook) };
+        hook(layout);
+    }
+
+    ptr::null_mut()
+}
+
+#[cfg(test)]
+mod test {
+    use core::{alloc::GlobalAlloc, sync::atomic::AtomicBool};
+
+    use super::*;
+    use crate::stack_allocator::StackAllocator;
+
+    #[test]
+    fn alloc_error_hook() {
+        // The hook is global and other tests fail allocations in parallel, so it only flags this one's odd size
+        static SAW_FAILURE: AtomicBool = AtomicBool::new(false);
+        set_alloc_error_hook(|layout| {
+            if layout.size() == 5001 {
+                SAW_FAILURE.store(true, Ordering::Relaxed);
+            }
+        });
+
+        let allocator = StackAllocator::new();
+        let layout = Layout::from_size_align(5001, 1).unwrap();
+        assert!(unsafe { allocator.alloc(layout) }.is_null());
+        assert!(SAW_FAILURE.load(Ordering::Relaxed));
+    }
+}
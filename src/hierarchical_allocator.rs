@@ -0,0 +1,152 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt,
+    ops::Deref,
+};
+
+use crate::page_allocator::{self, PAGE_SIZE, SubAllocator, YerbaAlloc};
+
+/// Chunk size used by `new`, in pages
+const DEFAULT_CHUNK_PAGES: usize = 64;
+
+/// Hands out child arenas, each a `LinkedListAllocator` over a chunk mapped by the parent
+/// Dropping a child unmaps its whole chunk at once, whatever is still allocated in it, like a region or an APR pool
+/// Children don't share anything but the parent, so they can be used and dropped independently
+pub struct HierarchicalAllocator {
+    parent: YerbaAlloc,
+    chunk_size: usize,
+}
+
+impl HierarchicalAllocator {
+    /// A parent handing out chunks of 64 pages
+    pub fn new() -> Self {
+        Self::with_chunk_size(*PAGE_SIZE * DEFAULT_CHUNK_PAGES)
+    }
+
+    /// A parent handing out chunks of `size` bytes, rounded up to whole pages
+    pub fn with_chunk_size(size: usize) -> Self {
+        HierarchicalAllocator {
+            parent: YerbaAlloc::new(),
+            chunk_size: size.max(1).next_multiple_of(*PAGE_SIZE),
+        }
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// The allocator the chunks are mapped from, its stats count one map and one unmap per child
+    pub fn parent(&self) -> &YerbaAlloc {
+        &self.parent
+    }
+
+    /// Maps a new chunk and sets up a child arena over it, None if the chunk can't be mapped
+    pub fn child(&self) -> Option<ChildArena<'_>> {
+        // Page aligned, as the child's arena has to be
+        let layout = Layout::from_size_align(self.chunk_size, *PAGE_SIZE).ok()?;
+        let base = unsafe { self.parent.alloc(layout) };
+        if base.is_null() {
+            return None;
+        }
+
+        Some(ChildArena {
+            arena: unsafe { page_allocator::from_mapping(base, self.chunk_size) },
+            parent: self,
+            base,
+            layout,
+        })
+    }
+}
+
+impl Default for HierarchicalAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for HierarchicalAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HierarchicalAllocator")
+            .field("chunk_size", &self.chunk_size)
+            .field("parent", &self.parent)
+            .finish()
+    }
+}
+
+/// A `LinkedListAllocator` over one chunk of a `HierarchicalAllocator`, dereferences to it
+/// Its chunk goes back to the parent when it's dropped, so nothing allocated from it may outlive it
+pub struct ChildArena<'a> {
+    arena: SubAllocator,
+    parent: &'a HierarchicalAllocator,
+    base: *mut u8,
+    layout: Layout,
+}
+
+impl Deref for ChildArena<'_> {
+    type Target = SubAllocator;
+
+    fn deref(&self) -> &Self::Target {
+        &self.arena
+    }
+}
+
+impl Drop for ChildArena<'_> {
+    fn drop(&mut self) {
+        // Large objects are mapped outside the chunk, everything else goes with it
+        self.arena.free_large_objects();
+        unsafe { self.parent.parent.dealloc(self.base, self.layout) };
+    }
+}
+
+impl fmt::Debug for ChildArena<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChildArena")
+            .field("chunk", &self.base)
+            .field("arena", &self.arena)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // The parent only holds atomics, its children sit behind the raw pointer to their chunk
+    const _: () = {
+        crate::assert_send::<HierarchicalAllocator>();
+        crate::assert_sync::<HierarchicalAllocator>();
+    };
+    crate::assert_not_impl!(ChildArena<'static>, Send);
+
+    #[test]
+    fn drop_child() {
+        let allocator = HierarchicalAllocator::with_chunk_size(*PAGE_SIZE * 4);
+        let layout = Layout::new::<[u8; 64]>();
+
+        let one = allocator.child().unwrap();
+        let two = allocator.child().unwrap();
+        assert_eq!(allocator.parent().stats().maps, 2);
+
+        unsafe {
+            let ptr = one.alloc(layout);
+            ptr.write_bytes(1, 64);
+            let kept = two.alloc(layout);
+            kept.write_bytes(2, 64);
+            assert!(two.as_ptr_range().contains(&kept));
+
+            // The whole chunk goes back in one unmap, without freeing the block first
+            drop(one);
+            assert_eq!(allocator.parent().stats().unmaps, 1);
+
+            assert!((0..64).all(|i| kept.add(i).read() == 2));
+            let more = two.alloc(layout);
+            assert!(!more.is_null());
+            two.dealloc(more, layout);
+            two.dealloc(kept, layout);
+            assert_eq!(two.validate(), Ok(()));
+        }
+
+        drop(two);
+        assert_eq!(allocator.parent().stats().unmaps, 2);
+    }
+}
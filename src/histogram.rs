@@ -0,0 +1,126 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+const BUCKETS: usize = 64;
+
+/// Wraps another allocator and counts its allocations by size
+/// Bucket `i` counts the sizes in `(2^(i - 1), 2^i]`, so 8 bytes lands in bucket 3 and 100 in bucket 7
+/// Reallocations are counted under their new size, failed allocations aren't counted
+pub struct Histogram<A: GlobalAlloc> {
+    inner: A,
+    buckets: [AtomicUsize; BUCKETS],
+}
+
+impl<A: GlobalAlloc> Histogram<A> {
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            buckets: [const { AtomicUsize::new(0) }; BUCKETS],
+        }
+    }
+
+    /// The wrapped allocator
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Number of allocations counted in each power of two bucket
+    pub fn histogram(&self) -> [usize; BUCKETS] {
+        core::array::from_fn(|i| self.buckets[i].load(Ordering::Relaxed))
+    }
+
+    fn bucket(size: usize) -> usize {
+        let bucket = (usize::BITS - size.saturating_sub(1).leading_zeros()) as usize;
+        bucket.min(BUCKETS - 1)
+    }
+
+    fn record(&self, ptr: *mut u8, size: usize) -> *mut u8 {
+        if !ptr.is_null() {
+            self.buckets[Self::bucket(size)].fetch_add(1, Ordering::Relaxed);
+        }
+
+        ptr
+    }
+}
+
+impl<A: GlobalAlloc + fmt::Debug> fmt::Debug for Histogram<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Histogram")
+            .field("inner", &self.inner)
+            .field("allocations", &self.histogram().iter().sum::<usize>())
+            .finish()
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Histogram<A> {
+    #[track_caller]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.record(unsafe { self.inner.alloc(layout) }, layout.size())
+    }
+
+    #[track_caller]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) }
+    }
+
+    #[track_caller]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.record(unsafe { self.inner.alloc_zeroed(layout) }, layout.size())
+    }
+
+    #[track_caller]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.record(
+            unsafe { self.inner.realloc(ptr, layout, new_size) },
+            new_size,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{linked_list_allocator::LinkedListAllocator, page_allocator::YerbaAlloc};
+
+    // The buckets are atomics, so it's as thread safe as the allocator it wraps
+    const _: () = {
+        crate::assert_send::<Histogram<YerbaAlloc>>();
+        crate::assert_sync::<Histogram<YerbaAlloc>>();
+    };
+    crate::assert_not_impl!(Histogram<LinkedListAllocator>, Sync);
+
+    #[test]
+    fn buckets() {
+        let allocator = Histogram::new(YerbaAlloc::new());
+
+        unsafe {
+            let ptrs = [8, 8, 100, 5000].map(|size| {
+                let layout = Layout::from_size_align(size, 8).unwrap();
+                (allocator.alloc(layout), layout)
+            });
+            for (ptr, layout) in ptrs {
+                allocator.dealloc(ptr, layout);
+            }
+        }
+
+        let histogram = allocator.histogram();
+        assert_eq!(histogram[3], 2);
+        assert_eq!(histogram[7], 1);
+        assert_eq!(histogram[13], 1);
+        assert_eq!(histogram.iter().sum::<usize>(), 4);
+    }
+
+    #[test]
+    fn bucket_bounds() {
+        type H = Histogram<YerbaAlloc>;
+        assert_eq!(H::bucket(0), 0);
+        assert_eq!(H::bucket(1), 0);
+        assert_eq!(H::bucket(2), 1);
+        assert_eq!(H::bucket(4096), 12);
+        assert_eq!(H::bucket(4097), 13);
+        assert_eq!(H::bucket(usize::MAX), BUCKETS - 1);
+    }
+}
@@ -0,0 +1,216 @@
+use core::{
+    alloc::Layout,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Number of live allocations tracked, release builds track none
+const SLOTS: usize = if cfg!(debug_assertions) { 64 } else { 0 };
+
+struct Slot {
+    /// Address of the allocation, 0 while the slot is empty
+    addr: AtomicUsize,
+    size: AtomicUsize,
+    /// Largest size it may be released with, when the allocator handed out more than was asked for
+    capacity: AtomicUsize,
+    align: AtomicUsize,
+}
+
+/// Remembers the layout live allocations were made with, so dealloc can check it's handed the same one
+/// It can't allocate, so only up to `SLOTS` allocations are tracked at once and the rest go unchecked
+pub(crate) struct LayoutTable {
+    slots: [Slot; SLOTS],
+}
+
+impl LayoutTable {
+    pub(crate) const fn new() -> Self {
+        LayoutTable {
+            slots: [const {
+                Slot {
+                    addr: AtomicUsize::new(0),
+                    size: AtomicUsize::new(0),
+                    capacity: AtomicUsize::new(0),
+                    align: AtomicUsize::new(0),
+                }
+            }; SLOTS],
+        }
+    }
+
+    /// Records the layout `ptr` was allocated with
+    pub(crate) fn record(&self, ptr: *mut u8, layout: Layout) {
+        if ptr.is_null() {
+            return;
+        }
+
+        let slot = self.slots.iter().find(|slot| {
+            slot.addr
+                .compare_exchange(0, ptr.addr(), Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        });
+        if let Some(slot) = slot {
+            slot.size.store(layout.size(), Ordering::Release);
+            slot.capacity.store(layout.size(), Ordering::Release);
+            slot.align.store(layout.align(), Ordering::Release);
+        }
+    }
+
+    /// Forgets `ptr`, panicking if it was recorded with a different layout
+    /// Any size between the recorded one and the capacity is accepted
+    #[track_caller]
+    pub(crate) fn release(&self, ptr: *mut u8, layout: Layout) {
+        let Some(slot) = self
+            .slots
+            .iter()
+            .find(|slot| slot.addr.load(Ordering::Acquire) == ptr.addr())
+        else {
+            return;
+        };
+
+        let size = slot.size.load(Ordering::Acquire);
+        let capacity = slot.capacity.load(Ordering::Acquire);
+        let align = slot.align.load(Ordering::Acquire);
+        slot.addr.store(0, Ordering::Release);
+
+        assert!(
+            (size..=capacity).contains(&layout.size()) && align == layout.align(),
+            "{ptr:p} was allocated with size {size} and align {align}, but released with {layout:?}"
+        );
+    }
+
+    /// Forgets every allocation at or above `addr`
+    pub(crate) fn release_from(&self, addr: usize) {
+        for slot in &self.slots {
+            let _ = slot
+                .addr
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |slot_addr| {
+                    (slot_addr >= addr).then_some(0)
+                });
+        }
+    }
+
+    /// Lets `ptr` be released with any size from `size` up to `capacity`
+    /// For blocks reported to the caller as larger than they were asked for
+    pub(crate) fn set_capacity(&self, ptr: *mut u8, size: usize, capacity: usize) {
+        let slot = self
+            .slots
+            .iter()
+            .find(|slot| slot.addr.load(Ordering::Acquire) == ptr.addr());
+        if let Some(slot) = slot {
+            slot.size.store(size, Ordering::Release);
+            slot.capacity.store(capacity, Ordering::Release);
+        }
+    }
+
+    /// Updates the alignment recorded for `ptr`, for blocks whose alignment changed without moving their data
+    pub(crate) fn set_align(&self, ptr: *mut u8, align: usize) {
+        if ptr.is_null() {
+            return;
+        }
+
+        let slot = self
+            .slots
+            .iter()
+            .find(|slot| slot.addr.load(Ordering::Acquire) == ptr.addr());
+        if let Some(slot) = slot {
+            slot.align.store(align, Ordering::Release);
+        }
+    }
+
+    /// Updates the size recorded for `ptr`, panicking if it was recorded with a size other than `old_size`
+    #[track_caller]
+    pub(crate) fn set_size(&self, ptr: *mut u8, old_size: usize, new_size: usize) {
+        let Some(slot) = self
+            .slots
+            .iter()
+            .find(|slot| slot.addr.load(Ordering::Acquire) == ptr.addr())
+        else {
+            return;
+        };
+
+        let size = slot.size.swap(new_size, Ordering::AcqRel);
+        slot.capacity.store(new_size, Ordering::Release);
+        assert!(
+            size == old_size,
+            "{ptr:p} was allocated with size {size}, but resized from {old_size}"
+        );
+    }
+
+    /// Checks `ptr` against the layout it was recorded with and records its new one
+    #[track_caller]
+    pub(crate) fn resize(
+        &self,
+        ptr: *mut u8,
+        old_layout: Layout,
+        new_ptr: *mut u8,
+        new_size: usize,
+    ) {
+        if new_ptr.is_null() {
+            return;
+        }
+
+        self.release(ptr, old_layout);
+        // `new_size` was already accepted by realloc, so it's a valid size for the alignment
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, old_layout.align()) };
+        self.record(new_ptr, new_layout);
+    }
+}
+
+#[cfg(all(test, debug_assertions))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn matching_layout() {
+        let table = LayoutTable::new();
+        let layout = Layout::new::<[u8; 16]>();
+        let ptr = 0x1000 as *mut u8;
+
+        table.record(ptr, layout);
+        table.release(ptr, layout);
+        // Released slots are empty, so the pointer is no longer checked
+        table.release(ptr, Layout::new::<u8>());
+    }
+
+    #[test]
+    #[should_panic(expected = "allocated with size 16")]
+    fn mismatched_layout() {
+        let table = LayoutTable::new();
+        let ptr = 0x1000 as *mut u8;
+
+        table.record(ptr, Layout::new::<[u8; 16]>());
+        table.release(ptr, Layout::new::<[u8; 8]>());
+    }
+
+    #[test]
+    #[should_panic(expected = "allocated with size 16")]
+    fn beyond_capacity() {
+        let table = LayoutTable::new();
+        let ptr = 0x1000 as *mut u8;
+
+        table.record(ptr, Layout::new::<[u8; 16]>());
+        table.set_capacity(ptr, 16, 64);
+        // 32 lies between the size and the capacity, 128 is past it
+        table.release(ptr, Layout::new::<[u8; 32]>());
+        table.record(ptr, Layout::new::<[u8; 16]>());
+        table.set_capacity(ptr, 16, 64);
+        table.release(ptr, Layout::new::<[u8; 128]>());
+    }
+
+    #[test]
+    fn release_from() {
+        let table = LayoutTable::new();
+        let layout = Layout::new::<[u8; 16]>();
+        let (low, high) = (0x1000 as *mut u8, 0x2000 as *mut u8);
+
+        table.record(low, layout);
+        table.record(high, layout);
+        table.release_from(0x2000);
+        // Only `high` was forgotten, so only `low` is still checked
+        table.release(high, Layout::new::<u8>());
+        assert!(
+            table
+                .slots
+                .iter()
+                .any(|slot| slot.addr.load(Ordering::Acquire) == 0x1000)
+        );
+    }
+}
@@ -1,9 +1,230 @@
 #![feature(allocator_api)]
-#![feature(slice_ptr_get)]
 // #![no_std]
 #![allow(static_mut_refs)]
 
-// pub mod linear_allocator;
+use core::alloc::Layout;
+
+pub mod allocator;
+pub mod boxed;
+pub mod const_arena;
+pub mod error;
+pub mod hierarchical_allocator;
+pub mod histogram;
+mod layout_table;
+pub mod linear_allocator;
 pub mod linked_list_allocator;
-// pub mod page_allocator;
+pub mod page_allocator;
+pub mod page_source;
+pub mod pool_allocator;
+pub mod rc;
+pub mod ring_allocator;
+pub mod sharded_allocator;
+pub mod side_table_allocator;
 pub mod stack_allocator;
+
+/// Sanitizes an alignment taken from a `Layout` before it's used in masks
+/// `Layout` guarantees a power of two, but a bad value smuggled in through unsafe code would produce garbage masks
+#[track_caller]
+pub(crate) fn checked_align(align: usize) -> usize {
+    let align = align.max(1);
+    debug_assert!(
+        align.is_power_of_two(),
+        "alignment {align} is not a power of two"
+    );
+
+    align
+}
+
+/// Number of bytes needed to bring `ptr` up to `align`, usize::MAX if it can't be aligned
+/// Most blocks already are, and the mask check is much cheaper than `align_offset`
+#[inline]
+pub(crate) fn padding_for(ptr: *const u8, align: usize) -> usize {
+    if ptr.addr() & (align - 1) == 0 {
+        return 0;
+    }
+
+    ptr.align_offset(align)
+}
+
+/// Overwrites `len` bytes at `ptr` with zeros when the `zeroize` feature is enabled
+/// Called on data that's being freed, so it doesn't linger in memory
+#[inline]
+pub(crate) unsafe fn zeroize(ptr: *mut u8, len: usize) {
+    if cfg!(feature = "zeroize") {
+        unsafe { ptr.write_bytes(0, len) };
+    }
+}
+
+/// Layout of a block reallocated from `layout` to `new_size` bytes
+/// None if the size overflows once rounded up to the alignment, which callers of `realloc` aren't meant to pass
+pub(crate) fn realloc_layout(layout: Layout, new_size: usize) -> Option<Layout> {
+    Layout::from_size_align(new_size, layout.align()).ok()
+}
+
+/// Layout of a C `calloc(count, size)` call, aligned for any fundamental type
+/// None if the total size is zero or overflows
+pub(crate) fn calloc_layout(count: usize, size: usize) -> Option<Layout> {
+    let size = count.checked_mul(size).filter(|&size| size != 0)?;
+    Layout::from_size_align(size, align_of::<libc::max_align_t>()).ok()
+}
+
+/// Compile-time checks of which auto traits the allocators implement
+#[cfg(test)]
+pub(crate) const fn assert_send<T: Send>() {}
+
+#[cfg(test)]
+pub(crate) const fn assert_sync<T: Sync>() {}
+
+/// Fails to compile if `$ty` implements `$trait`
+/// Both impls apply to a type that does, so the call to `check` can't be resolved
+#[cfg(test)]
+macro_rules! assert_not_impl {
+    ($ty:ty, $trait:path) => {
+        const _: fn() = || {
+            trait AmbiguousIfImpl<A> {
+                fn check() {}
+            }
+            impl<T: ?Sized> AmbiguousIfImpl<()> for T {}
+            impl<T: ?Sized + $trait> AmbiguousIfImpl<u8> for T {}
+            let _ = <$ty as AmbiguousIfImpl<_>>::check;
+        };
+    };
+}
+#[cfg(test)]
+pub(crate) use assert_not_impl;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn zero_align() {
+        assert_eq!(checked_align(0), 1);
+        assert_eq!(checked_align(16), 16);
+    }
+
+    #[test]
+    fn calloc_overflow() {
+        assert!(calloc_layout(usize::MAX, 2).is_none());
+        assert!(calloc_layout(0, 8).is_none());
+        assert_eq!(calloc_layout(1000, 8).unwrap().size(), 8000);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "not a power of two")]
+    fn invalid_align() {
+        checked_align(24);
+    }
+
+    #[test]
+    fn padding() {
+        let base = 0x1000 as *const u8;
+        assert_eq!(padding_for(base, 1), 0);
+        assert_eq!(padding_for(base, 64), 0);
+
+        for offset in 1..64 {
+            let ptr = base.wrapping_add(offset);
+            let padding = padding_for(ptr, 64);
+            assert_eq!(padding, ptr.align_offset(64));
+            assert!(ptr.wrapping_add(padding).addr().is_multiple_of(64));
+        }
+    }
+
+    #[test]
+    fn debug_impls() {
+        use core::fmt::{self, Write};
+
+        /// Formats into a fixed buffer, without allocating
+        struct FixedBuf {
+            buf: [u8; 512],
+            len: usize,
+        }
+
+        impl Write for FixedBuf {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                let end = self.len + s.len();
+                self.buf
+                    .get_mut(self.len..end)
+                    .ok_or(fmt::Error)?
+                    .copy_from_slice(s.as_bytes());
+                self.len = end;
+                Ok(())
+            }
+        }
+
+        fn check(allocator: &dyn fmt::Debug, fields: &[&str]) {
+            let mut out = FixedBuf {
+                buf: [0; 512],
+                len: 0,
+            };
+            write!(out, "{allocator:?}").unwrap();
+            let out = core::str::from_utf8(&out.buf[..out.len]).unwrap();
+            for field in fields {
+                assert!(out.contains(field), "{out} is missing {field}");
+            }
+        }
+
+        let linked_list = linked_list_allocator::LinkedListAllocator::new();
+        let side_table = side_table_allocator::SideTableAllocator::new();
+        check(
+            &stack_allocator::StackAllocator::new(),
+            &["StackAllocator", "capacity: 4096", "used: 0"],
+        );
+        check(&stack_allocator::GrowableStack::new(), &["stack", "heap"]);
+        check(
+            &linear_allocator::LinearAllocator::new(),
+            &["LinearAllocator", "capacity: 4096", "used: 0"],
+        );
+        check(
+            &linear_allocator::FrameAllocator::new(),
+            &["arena", "in_frame: false"],
+        );
+        check(
+            &linear_allocator::TypedArena::<u64>::new(),
+            &["TypedArena", "capacity"],
+        );
+        check(
+            &linked_list,
+            &["LinkedListAllocator", "capacity", "used: 0", "blocks: 1"],
+        );
+        check(
+            &page_allocator::YerbaAlloc::new(),
+            &["YerbaAlloc", "used: 0", "maps: 0"],
+        );
+        check(&side_table, &["SideTableAllocator", "used: 0", "blocks: 1"]);
+        let growable_linear = linear_allocator::GrowableLinear::new();
+        check(
+            &growable_linear,
+            &["GrowableLinear", "committed", "used: 0"],
+        );
+        check(
+            &ring_allocator::RingAllocator::new(),
+            &["RingAllocator", "head: 0"],
+        );
+        check(
+            &const_arena::ConstArena::<64>::new(),
+            &["ConstArena", "capacity: 64", "used: 0"],
+        );
+        check(
+            &histogram::Histogram::new(page_allocator::YerbaAlloc::new()),
+            &["inner", "allocations: 0"],
+        );
+        check(
+            &hierarchical_allocator::HierarchicalAllocator::new(),
+            &["HierarchicalAllocator", "chunk_size", "maps: 0"],
+        );
+        check(
+            &pool_allocator::PoolAllocator::<64, 4>::new(),
+            &["PoolAllocator", "capacity: 4", "available: 4"],
+        );
+        check(
+            &sharded_allocator::ShardedAllocator::<2>::new(),
+            &["LinkedListAllocator", "blocks: 1"],
+        );
+
+        linked_list.free_allocator();
+        side_table.free_allocator();
+        growable_linear.free_allocator();
+    }
+}
@@ -1,71 +1,1071 @@
 use core::{
-    alloc::GlobalAlloc,
+    alloc::{GlobalAlloc, Layout},
     cell::UnsafeCell,
-    ops::AddAssign,
+    cmp, fmt,
+    marker::PhantomData,
+    mem::MaybeUninit,
+    ops::Range,
     ptr,
-    sync::atomic::{AtomicU8, AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+};
+
+use crate::{
+    error::AllocFailure,
+    page_source::{LibcMmap, PageSource},
 };
 
 const ARENA_SIZE: usize = 4096;
 const MAX_SUPPORTED_ALIGN: usize = 4096;
+/// Address space a `GrowableLinear` reserves up front and grows into
+const GROWABLE_RESERVE: usize = 64 * 1024 * 1024;
+
+/// Runs a block with a fresh `LinearAllocator` as its scratch arena and evaluates to the block's value
+/// The arena is reset once the block finishes, so nothing allocated from it may escape
+///
+/// ```
+/// use core::alloc::{GlobalAlloc, Layout};
+/// use yerba::scoped_arena;
+///
+/// let sum = scoped_arena!(arena => {
+///     let layout = Layout::array::<u32>(4).unwrap();
+///     let ptr = unsafe { arena.alloc(layout) }.cast::<u32>();
+///     (0..4).for_each(|i| unsafe { ptr.add(i).write(i as u32) });
+///     (0..4).map(|i| unsafe { ptr.add(i).read() }).sum::<u32>()
+/// });
+/// assert_eq!(sum, 6);
+/// ```
+#[macro_export]
+macro_rules! scoped_arena {
+    ($arena:ident => $body:block) => {{
+        let $arena = $crate::linear_allocator::LinearAllocator::new();
+        let result = $body;
+        // The arena is local to the macro, so nothing outside the block can use its blocks
+        unsafe { $arena.reset() };
+        result
+    }};
+}
+
+/// Which way a `LinearAllocator` bumps its frontier through the arena
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BumpDirection {
+    /// From the end of the arena towards its start, the default
+    Down,
+    /// From the start of the arena towards its end, the top block can then grow in place
+    Up,
+}
 
+/// Bumps allocations through a fixed arena, downwards from its end unless built `with_direction(BumpDirection::Up)`
+/// Individual allocations can't be freed, the arena is released all at once
 pub struct LinearAllocator {
     arena: UnsafeCell<[u8; ARENA_SIZE]>,
-    remaining: AtomicUsize,
+    direction: BumpDirection,
+    /// Offset of the boundary between used and free space
+    /// Bumping down, everything below it is free, bumping up, everything above it is
+    frontier: AtomicUsize,
+    /// Callback registered with `on_oom`, a `fn()` stored as a pointer, null if there is none
+    on_oom: AtomicPtr<()>,
+}
+
+impl LinearAllocator {
+    /// Number of bytes in the arena
+    pub const CAPACITY: usize = ARENA_SIZE;
+    /// Largest alignment `alloc` will satisfy, larger requests return null
+    pub const MAX_ALIGN: usize = MAX_SUPPORTED_ALIGN;
+
+    pub const fn new() -> Self {
+        Self::with_direction(BumpDirection::Down)
+    }
+
+    /// An empty arena that bumps allocations in `direction`
+    pub const fn with_direction(direction: BumpDirection) -> Self {
+        LinearAllocator {
+            arena: UnsafeCell::new([0; ARENA_SIZE]),
+            direction,
+            frontier: AtomicUsize::new(Self::empty_frontier(direction)),
+            on_oom: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    pub fn direction(&self) -> BumpDirection {
+        self.direction
+    }
+
+    /// Number of bytes between the start of the bump and the frontier, including alignment padding
+    pub fn used_bytes(&self) -> usize {
+        let frontier = self.frontier.load(Ordering::Relaxed);
+        match self.direction {
+            BumpDirection::Down => ARENA_SIZE - frontier,
+            BumpDirection::Up => frontier,
+        }
+    }
+
+    /// Registers `cb` to be called the next time `alloc` runs out of room in the arena, just before it returns null
+    /// It's called once and then forgotten, register it again to hear about the next time
+    /// Requests that could never succeed, like an alignment over `MAX_ALIGN`, don't count as running out
+    pub fn on_oom(&self, cb: fn()) {
+        self.on_oom.store(cb as *mut (), Ordering::Release);
+    }
+
+    /// Allocates zeroed memory for `count` elements of `size` bytes, like C's `calloc`
+    /// Returns null if the total size is zero or overflows
+    pub fn calloc(&self, count: usize, size: usize) -> *mut u8 {
+        match crate::calloc_layout(count, size) {
+            Some(layout) => unsafe { self.alloc_zeroed(layout) },
+            None => ptr::null_mut(),
+        }
+    }
+
+    /// Allocates room for a `T`, left uninitialized, null if it doesn't fit
+    /// Free it with `dealloc` and `Layout::new::<T>()`
+    pub fn alloc_uninit<T>(&self) -> *mut MaybeUninit<T> {
+        unsafe { self.alloc(Layout::new::<T>()) }.cast()
+    }
+
+    /// Allocates room for `len` values of `T`, left uninitialized, null if it doesn't fit or the size overflows
+    /// Free it with `dealloc` and `Layout::array::<T>(len)`
+    pub fn alloc_uninit_slice<T>(&self, len: usize) -> *mut [MaybeUninit<T>] {
+        let ptr = match Layout::array::<T>(len) {
+            Ok(layout) => unsafe { self.alloc(layout) },
+            Err(_) => ptr::null_mut(),
+        };
+        ptr::slice_from_raw_parts_mut(ptr.cast(), len)
+    }
+
+    /// Allocates the block starting `offset` bytes into the arena
+    /// Returns null if that address isn't aligned for `layout` or the block would overlap one already handed out
+    /// The frontier moves past the block, so the space between it and the previous frontier is skipped
+    ///
+    /// # Safety
+    /// Same as `GlobalAlloc::alloc`
+    pub unsafe fn alloc_at(&self, offset: usize, layout: core::alloc::Layout) -> *mut u8 {
+        let align = crate::checked_align(layout.align());
+        let ptr = self.base_ptr().wrapping_add(offset);
+        if !ptr.addr().is_multiple_of(align) {
+            return ptr::null_mut();
+        }
+
+        let placed = self
+            .frontier
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |frontier| {
+                let end = offset.checked_add(layout.size())?;
+                match self.direction {
+                    BumpDirection::Down => (end <= frontier).then_some(offset),
+                    BumpDirection::Up => (offset >= frontier && end <= ARENA_SIZE).then_some(end),
+                }
+            });
+        if placed.is_err() {
+            return ptr::null_mut();
+        }
+
+        ptr
+    }
+
+    /// Releases every allocation at once and scrubs the arena
+    ///
+    /// # Safety
+    /// Blocks allocated from the arena must not be used afterwards, they're handed out again
+    pub unsafe fn reset(&self) {
+        self.frontier
+            .store(Self::empty_frontier(self.direction), Ordering::Relaxed);
+        unsafe { self.arena.get().write([0; ARENA_SIZE]) };
+    }
+
+    /// The whole arena, every block this allocator hands out lies within it
+    pub fn as_ptr_range(&self) -> Range<*mut u8> {
+        let start = self.base_ptr();
+        start..unsafe { start.add(ARENA_SIZE) }
+    }
+
+    /// Offset of `ptr` from the start of the arena, None if it lies outside it
+    /// Offsets stay valid when the arena's contents are saved and loaded somewhere else, unlike pointers
+    pub fn offset_of(&self, ptr: *const u8) -> Option<usize> {
+        let range = self.as_ptr_range();
+        range
+            .contains(&ptr.cast_mut())
+            .then(|| ptr.addr() - range.start.addr())
+    }
+
+    /// Pointer `offset` bytes into the arena, the inverse of `offset_of`, null if it lies outside it
+    pub fn ptr_of(&self, offset: usize) -> *mut u8 {
+        let range = self.as_ptr_range();
+        if offset >= range.end.addr() - range.start.addr() {
+            return ptr::null_mut();
+        }
+
+        unsafe { range.start.add(offset) }
+    }
+
+    /// Lets the OS drop the physical pages under the part of the arena that isn't in use
+    /// That's the far side of the frontier, the front of the arena when bumping down and the back when bumping up
+    /// Only whole pages are released, they read as zero when touched again
+    /// Returns whether `madvise` succeeded
+    #[cfg(target_os = "linux")]
+    pub fn advise_dontneed(&self) -> bool {
+        let page_size = *crate::page_allocator::PAGE_SIZE;
+        let frontier = self
+            .base_ptr()
+            .wrapping_add(self.frontier.load(Ordering::Relaxed));
+        let (start, end) = match self.direction {
+            BumpDirection::Down => (self.base_ptr(), frontier),
+            BumpDirection::Up => (frontier, self.base_ptr().wrapping_add(ARENA_SIZE)),
+        };
+        let start = start.wrapping_add(crate::padding_for(start, page_size));
+        let end = end.wrapping_sub(end.addr() % page_size);
+        if end <= start {
+            return true;
+        }
+
+        let len = end.addr() - start.addr();
+        unsafe { libc::madvise(start.cast(), len, libc::MADV_DONTNEED) == 0 }
+    }
+
+    /// Takes and calls the callback registered with `on_oom`, if any
+    fn out_of_memory(&self) {
+        let cb = self.on_oom.swap(ptr::null_mut(), Ordering::AcqRel);
+        if !cb.is_null() {
+            let cb = unsafe { core::mem::transmute::<*mut (), fn()>(cb) };
+            cb();
+        }
+    }
+
+    /// Whether the block at `ptr` can hold `new_size` bytes without moving
+    /// Bumping up, the top block is resized by moving the frontier, if the arena has room
+    /// Bumping down, the end of the top block abuts the one allocated before it and no block can grow in place,
+    /// this only returns true when the size doesn't change
+    /// The caller has to allocate and copy itself, or use `realloc`, which shifts the top block down
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by this allocator with `layout`
+    pub unsafe fn try_grow_in_place(
+        &self,
+        ptr: *mut u8,
+        layout: core::alloc::Layout,
+        new_size: usize,
+    ) -> bool {
+        if new_size == layout.size() {
+            return true;
+        }
+
+        self.direction == BumpDirection::Up && self.resize_top_up(ptr, layout.size(), new_size)
+    }
+
+    /// Moves the frontier to the end of the top block resized to `new_size`, bumping up
+    /// Fails if `ptr` isn't the top block or the arena doesn't have room
+    fn resize_top_up(&self, ptr: *mut u8, size: usize, new_size: usize) -> bool {
+        let start = ptr.addr().wrapping_sub(self.base_ptr().addr());
+        self.frontier
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |frontier| {
+                let new_end = start.checked_add(new_size)?;
+                (frontier == start + size && new_end <= ARENA_SIZE).then_some(new_end)
+            })
+            .is_ok()
+    }
+
+    const fn empty_frontier(direction: BumpDirection) -> usize {
+        match direction {
+            BumpDirection::Down => ARENA_SIZE,
+            BumpDirection::Up => 0,
+        }
+    }
+
+    fn base_ptr(&self) -> *mut u8 {
+        self.arena.get().cast()
+    }
+
+    /// Whether `ptr`, a block of `size` bytes, is the most recent allocation, which sits at the frontier
+    fn is_top(&self, ptr: *mut u8, size: usize) -> bool {
+        let frontier = self.base_ptr().addr() + self.frontier.load(Ordering::Relaxed);
+        match self.direction {
+            BumpDirection::Down => ptr.addr() == frontier,
+            BumpDirection::Up => ptr.addr() + size == frontier,
+        }
+    }
+}
+
+impl Default for LinearAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for LinearAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LinearAllocator")
+            .field("capacity", &ARENA_SIZE)
+            .field("used", &self.used_bytes())
+            .field("direction", &self.direction)
+            .finish()
+    }
+}
+
+/// A `LinearAllocator` for scratch memory that lives for a single frame
+/// Allocations are made between `begin_frame` and `end_frame`, which releases all of them at once
+pub struct FrameAllocator {
+    arena: LinearAllocator,
+    in_frame: AtomicBool,
+}
+
+impl FrameAllocator {
+    pub const fn new() -> Self {
+        FrameAllocator {
+            arena: LinearAllocator::new(),
+            in_frame: AtomicBool::new(false),
+        }
+    }
+
+    /// Starts a frame, panics if the previous one wasn't ended
+    #[track_caller]
+    pub fn begin_frame(&self) {
+        let in_frame = self.in_frame.swap(true, Ordering::Relaxed);
+        assert!(!in_frame, "frame begun before the previous one ended");
+    }
+
+    /// Ends the frame and releases everything allocated during it
+    /// Panics if no frame was begun
+    ///
+    /// # Safety
+    /// Blocks allocated during the frame must not be used afterwards
+    #[track_caller]
+    pub unsafe fn end_frame(&self) {
+        let in_frame = self.in_frame.swap(false, Ordering::Relaxed);
+        assert!(in_frame, "frame ended without being begun");
+        unsafe { self.arena.reset() };
+    }
+
+    /// The arena backing the current frame
+    pub fn arena(&self) -> &LinearAllocator {
+        &self.arena
+    }
+}
+
+impl Default for FrameAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for FrameAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrameAllocator")
+            .field("arena", &self.arena)
+            .field("in_frame", &self.in_frame.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+unsafe impl GlobalAlloc for FrameAllocator {
+    #[track_caller]
+    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
+        debug_assert!(
+            self.in_frame.load(Ordering::Relaxed),
+            "allocated outside of a frame"
+        );
+        unsafe { self.arena.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
+        unsafe { self.arena.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: core::alloc::Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        unsafe { self.arena.realloc(ptr, layout, new_size) }
+    }
+}
+
+/// Bump allocates values of a single type from a `LinearAllocator`, handing out references to them
+/// Values are never dropped, resetting the arena just forgets them
+/// Suits trees of plain data like AST nodes that are thrown away all at once
+pub struct TypedArena<T> {
+    arena: LinearAllocator,
+    _marker: PhantomData<T>,
+}
+
+impl<T> TypedArena<T> {
+    pub const fn new() -> Self {
+        TypedArena {
+            arena: LinearAllocator::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Moves `value` into the arena, None if it's full
+    /// References stay valid until the arena is reset, which needs exclusive access
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc(&self, value: T) -> Option<&mut T> {
+        let ptr = unsafe { self.arena.alloc(core::alloc::Layout::new::<T>()) }.cast::<T>();
+        if ptr.is_null() {
+            return None;
+        }
+
+        unsafe {
+            ptr.write(value);
+            Some(&mut *ptr)
+        }
+    }
+
+    /// Releases every value at once without dropping them
+    pub fn reset(&mut self) {
+        // Every reference into the arena borrows it, so none are left once it's borrowed mutably
+        unsafe { self.arena.reset() };
+    }
+}
+
+impl<T> Default for TypedArena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> fmt::Debug for TypedArena<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedArena")
+            .field("arena", &self.arena)
+            .finish()
+    }
+}
+
+/// Bumps allocations upwards through an mmap'd arena, committing pages as the frontier reaches them
+/// Like `LinearAllocator` with `BumpDirection::Up`, but not capped at 4 KiB, the top block grows into new pages in place
+/// Individual allocations can't be freed, the arena is released all at once
+pub struct GrowableLinear<S: PageSource = LibcMmap> {
+    base: *mut u8,
+    /// Offset of the end of the used space
+    frontier: AtomicUsize,
+    /// Number of bytes from the base that are backed, a whole number of pages
+    committed: AtomicUsize,
+    page_size: usize,
+    source: S,
+}
+
+impl GrowableLinear {
+    /// Largest alignment `alloc` will satisfy, larger requests return null
+    pub const MAX_ALIGN: usize = MAX_SUPPORTED_ALIGN;
+
+    pub fn new() -> Self {
+        Self::with_source(LibcMmap)
+    }
+
+    /// Like `new`, but reports a failure to reserve or commit the arena instead of panicking
+    pub fn try_new() -> Result<Self, AllocFailure> {
+        Self::try_with_source(LibcMmap)
+    }
+}
+
+impl Default for GrowableLinear {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: PageSource> fmt::Debug for GrowableLinear<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GrowableLinear")
+            .field("capacity", &GROWABLE_RESERVE)
+            .field("committed", &self.committed_bytes())
+            .field("used", &self.used_bytes())
+            .finish()
+    }
+}
+
+impl<S: PageSource> GrowableLinear<S> {
+    /// Reserves the arena from `source` and backs its first 4 KiB, as much as a `LinearAllocator` holds
+    /// Panics if either fails, see `try_with_source`
+    pub fn with_source(source: S) -> Self {
+        match Self::try_with_source(source) {
+            Ok(allocator) => allocator,
+            Err(failure) => panic!("Failed to set up the arena: {failure}"),
+        }
+    }
+
+    /// Reserves the arena from `source` and backs its first 4 KiB, reporting a failure instead of panicking
+    pub fn try_with_source(source: S) -> Result<Self, AllocFailure> {
+        let page_size = *crate::page_allocator::PAGE_SIZE;
+        let committed = ARENA_SIZE.next_multiple_of(page_size);
+        unsafe {
+            let base = source.reserve(GROWABLE_RESERVE);
+            if base.is_null() {
+                return Err(AllocFailure::ReserveFailed);
+            }
+            if source.map(base, committed).is_null() {
+                source.release(base, GROWABLE_RESERVE);
+                return Err(AllocFailure::CommitFailed);
+            }
+
+            Ok(Self {
+                base,
+                frontier: AtomicUsize::new(0),
+                committed: AtomicUsize::new(committed),
+                page_size,
+                source,
+            })
+        }
+    }
+
+    /// Number of bytes below the frontier, including alignment padding
+    pub fn used_bytes(&self) -> usize {
+        self.frontier.load(Ordering::Relaxed)
+    }
+
+    /// Number of bytes backed so far, pages stay committed until the allocator is freed
+    pub fn committed_bytes(&self) -> usize {
+        self.committed.load(Ordering::Relaxed)
+    }
+
+    /// The committed part of the arena, grows as the frontier moves into new pages
+    pub fn as_ptr_range(&self) -> Range<*mut u8> {
+        self.base..self.base.wrapping_add(self.committed_bytes())
+    }
+
+    /// Frees every allocation at once and zeroes the used space, the committed pages are kept for reuse
+    ///
+    /// # Safety
+    /// Blocks allocated from the arena must not be used afterwards, they're handed out again
+    pub unsafe fn reset(&self) {
+        unsafe { self.base.write_bytes(0, self.used_bytes()) };
+        self.frontier.store(0, Ordering::Relaxed);
+    }
+
+    pub fn free_allocator(self) {
+        unsafe { self.source.release(self.base, GROWABLE_RESERVE) };
+    }
+
+    /// Backs the arena up to `end`, false if it would run past the reservation or the source fails
+    fn commit(&self, end: usize) -> bool {
+        let committed = self.committed_bytes();
+        if end <= committed {
+            return true;
+        }
+        let Some(new_committed) = end.checked_next_multiple_of(self.page_size) else {
+            return false;
+        };
+        if new_committed > GROWABLE_RESERVE {
+            return false;
+        }
+
+        let extension = unsafe {
+            self.source
+                .map(self.base.add(committed), new_committed - committed)
+        };
+        if extension.is_null() {
+            return false;
+        }
+        self.committed.store(new_committed, Ordering::Relaxed);
+
+        true
+    }
+
+    /// Whether the block of `size` bytes at `ptr` ends at the frontier
+    fn is_top(&self, ptr: *mut u8, size: usize) -> bool {
+        ptr.addr().wrapping_sub(self.base.addr()).wrapping_add(size) == self.used_bytes()
+    }
+}
+
+unsafe impl<S: PageSource> GlobalAlloc for GrowableLinear<S> {
+    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
+        let align = crate::checked_align(layout.align());
+        if align > MAX_SUPPORTED_ALIGN {
+            return ptr::null_mut();
+        }
+
+        let frontier = self.used_bytes();
+        let start = frontier + crate::padding_for(self.base.wrapping_add(frontier), align);
+        let Some(end) = start.checked_add(layout.size()) else {
+            return ptr::null_mut();
+        };
+        if !self.commit(end) {
+            return ptr::null_mut();
+        }
+        self.frontier.store(end, Ordering::Relaxed);
+
+        unsafe { self.base.add(start) }
+    }
+
+    /// Does nothing, blocks are only freed by `reset`
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: core::alloc::Layout) {}
+
+    /// The top block is resized in place, committing pages as it grows, other blocks are copied to the top
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: core::alloc::Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        let Some(new_layout) = crate::realloc_layout(layout, new_size) else {
+            return ptr::null_mut();
+        };
+        if self.is_top(ptr, layout.size()) {
+            let start = ptr.addr() - self.base.addr();
+            let Some(end) = start.checked_add(new_size) else {
+                return ptr::null_mut();
+            };
+            if !self.commit(end) {
+                return ptr::null_mut();
+            }
+            self.frontier.store(end, Ordering::Relaxed);
+
+            return ptr;
+        }
+
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe { ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(layout.size(), new_size)) };
+        }
+
+        new_ptr
+    }
 }
 
 unsafe impl GlobalAlloc for LinearAllocator {
+    #[track_caller]
     unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
         let size = layout.size();
-        let align = layout.align();
+        let align = crate::checked_align(layout.align());
         if align > MAX_SUPPORTED_ALIGN {
             return ptr::null_mut();
         }
 
-        let base_ptr = self.arena.get() as *mut [u8; ARENA_SIZE];
+        let base_addr = self.base_ptr().addr();
         let mut ptr: *mut u8 = ptr::null_mut();
         if self
-            .remaining
-            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |mut remaining| {
-                if ARENA_SIZE - remaining >= size {
-                    return None;
-                }
+            .frontier
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |frontier| {
+                // The arena itself isn't aligned, so round the address rather than the offset
+                let (start, frontier) = match self.direction {
+                    BumpDirection::Down => {
+                        if frontier < size {
+                            return None;
+                        }
 
-                let align_mask_to_round_down = !(align - 1);
-                remaining -= size;
-                remaining &= align_mask_to_round_down;
+                        let align_mask_to_round_down = !(align - 1);
+                        let addr = (base_addr + frontier - size) & align_mask_to_round_down;
+                        if addr < base_addr {
+                            return None;
+                        }
+                        (addr - base_addr, addr - base_addr)
+                    }
+                    BumpDirection::Up => {
+                        let addr = (base_addr + frontier).next_multiple_of(align);
+                        let start = addr - base_addr;
+                        let end = start.checked_add(size).filter(|&end| end <= ARENA_SIZE)?;
+                        (start, end)
+                    }
+                };
 
-                ptr = unsafe { base_ptr.cast::<u8>().add(remaining) };
-                Some(remaining)
+                ptr = unsafe { self.base_ptr().add(start) };
+                Some(frontier)
             })
             .is_err()
         {
+            self.out_of_memory();
             return ptr::null_mut();
         };
 
-        return ptr;
+        ptr
     }
 
-    /// Deallocates the entire arena at once
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: core::alloc::Layout) {
-        self.remaining
-            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |_| Some(ARENA_SIZE));
-        let ptr = self.arena.get() as *mut [u8; ARENA_SIZE];
-        unsafe {
-            ptr.write([0; ARENA_SIZE]);
-        }
-    }
+    /// Does nothing, individual blocks can't be freed
+    /// Their space is only reclaimed when the whole arena is `reset`
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: core::alloc::Layout) {}
 
+    /// Resizes the most recent allocation by moving the frontier, in either direction
+    /// Bumping down its data is shifted along with it, bumping up it stays put
+    /// Any other allocation is copied to a new one when it grows, the old block stays reserved until the arena is freed
     unsafe fn realloc(
         &self,
         ptr: *mut u8,
         layout: core::alloc::Layout,
         new_size: usize,
     ) -> *mut u8 {
-        todo!()
+        let size = layout.size();
+        if new_size == size {
+            return ptr;
+        }
+
+        let Some(new_layout) = crate::realloc_layout(layout, new_size) else {
+            return ptr::null_mut();
+        };
+        if self.direction == BumpDirection::Up && self.is_top(ptr, size) {
+            if self.resize_top_up(ptr, size, new_size) {
+                return ptr;
+            }
+            self.out_of_memory();
+            return ptr::null_mut();
+        }
+        if self.is_top(ptr, size) {
+            // Release the top block so the new one is bumped from where it ends
+            let end = ptr.addr() + size - self.base_ptr().addr();
+            self.frontier.store(end, Ordering::Relaxed);
+
+            let new_ptr = unsafe { self.alloc(new_layout) };
+            if new_ptr.is_null() {
+                self.frontier
+                    .store(ptr.addr() - self.base_ptr().addr(), Ordering::Relaxed);
+                return ptr::null_mut();
+            }
+
+            // The regions overlap, the new block starts below the old one when growing and above it when shrinking
+            unsafe { ptr::copy(ptr, new_ptr, cmp::min(size, new_size)) };
+            return new_ptr;
+        }
+        if new_size < size {
+            return ptr;
+        }
+
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe { ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(size, new_size)) };
+        }
+
+        new_ptr
     }
 
+    #[track_caller]
     unsafe fn alloc_zeroed(&self, layout: core::alloc::Layout) -> *mut u8 {
-        todo!()
+        let size = layout.size();
+        let ptr = unsafe { self.alloc(layout) };
+        if ptr.is_null() {
+            return ptr;
+        }
+
+        // A single `write_bytes` lowers to `memset`, several times faster than zeroing byte by byte
+        unsafe { ptr.write_bytes(0, size) };
+
+        ptr
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::alloc::Layout;
+
+    use super::*;
+
+    // The arena can move to another thread, but isn't shared, so none of them can be a global allocator
+    const _: () = {
+        crate::assert_send::<LinearAllocator>();
+        crate::assert_send::<FrameAllocator>();
+        crate::assert_send::<TypedArena<u64>>();
+    };
+    crate::assert_not_impl!(LinearAllocator, Sync);
+    crate::assert_not_impl!(FrameAllocator, Sync);
+    crate::assert_not_impl!(TypedArena<u64>, Sync);
+    // Sits behind a raw pointer to its reservation, like `LinkedListAllocator`
+    crate::assert_not_impl!(GrowableLinear, Send);
+    crate::assert_not_impl!(GrowableLinear, Sync);
+
+    #[test]
+    fn alloc_chunks() {
+        let allocator = LinearAllocator::new();
+        let layout = Layout::new::<[u8; 16]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            assert!(!one.is_null());
+
+            let two = allocator.alloc(layout);
+            assert!(!two.is_null());
+            assert!(two.addr() + 16 <= one.addr());
+
+            let too_large = allocator.alloc(Layout::new::<[u8; ARENA_SIZE]>());
+            assert!(too_large.is_null());
+
+            allocator.dealloc(two, layout);
+        }
+    }
+
+    #[test]
+    fn limits() {
+        assert_ne!(LinearAllocator::CAPACITY, 0);
+        assert!(LinearAllocator::MAX_ALIGN.is_power_of_two());
+    }
+
+    #[test]
+    fn realloc_same_size() {
+        let allocator = LinearAllocator::new();
+        let layout = Layout::new::<[u8; 16]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            assert!(!one.is_null());
+
+            let frontier = allocator.frontier.load(Ordering::Relaxed);
+            assert_eq!(allocator.realloc(one, layout, layout.size()), one);
+            assert_eq!(allocator.frontier.load(Ordering::Relaxed), frontier);
+        }
+    }
+
+    #[test]
+    fn calloc() {
+        let allocator = LinearAllocator::new();
+
+        assert!(allocator.calloc(usize::MAX, 2).is_null());
+        assert!(allocator.calloc(1000, 8).is_null());
+
+        let ptr = allocator.calloc(500, 8);
+        assert!(!ptr.is_null());
+        unsafe { assert!((0..4000).all(|i| ptr.add(i).read() == 0)) };
+    }
+
+    #[test]
+    fn ptr_range() {
+        let allocator = LinearAllocator::new();
+        let layout = Layout::new::<[u8; 16]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            let range = allocator.as_ptr_range();
+            assert!(range.contains(&one));
+            assert!(one.add(layout.size()) <= range.end);
+        }
+    }
+
+    #[test]
+    fn realloc_shrink_top() {
+        let allocator = LinearAllocator::new();
+        let layout = Layout::new::<[u8; 128]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            assert!(!one.is_null());
+            (0..32).for_each(|i| one.add(i).write(i as u8));
+            let frontier = allocator.frontier.load(Ordering::Relaxed);
+
+            let shrunk = allocator.realloc(one, layout, 32);
+            assert!(!shrunk.is_null());
+            assert_eq!(allocator.frontier.load(Ordering::Relaxed), frontier + 96);
+            (0..32).for_each(|i| assert_eq!(shrunk.add(i).read(), i as u8));
+        }
+    }
+
+    #[test]
+    fn dealloc_keeps_blocks() {
+        let allocator = LinearAllocator::new();
+        let layout = Layout::new::<[u8; 16]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            one.write_bytes(0xAB, 16);
+            let two = allocator.alloc(layout);
+            allocator.dealloc(two, layout);
+
+            assert!((0..16).all(|i| one.add(i).read() == 0xAB));
+            assert!(allocator.alloc(layout) < two);
+        }
+    }
+
+    #[test]
+    fn frames() {
+        let allocator = FrameAllocator::new();
+        let layout = Layout::new::<[u8; 64]>();
+
+        let mut frames = [[ptr::null_mut(); 3]; 2];
+        for frame in &mut frames {
+            allocator.begin_frame();
+            for ptr in frame.iter_mut() {
+                *ptr = unsafe { allocator.alloc(layout) };
+                assert!(!ptr.is_null());
+            }
+            unsafe { allocator.end_frame() };
+        }
+
+        // Each frame starts from an empty arena, so the addresses repeat
+        assert_eq!(frames[0], frames[1]);
+    }
+
+    #[test]
+    #[should_panic(expected = "frame ended without being begun")]
+    fn unbegun_frame() {
+        unsafe { FrameAllocator::new().end_frame() };
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn advise_dontneed() {
+        let allocator = Box::new(LinearAllocator::new());
+        assert!(allocator.advise_dontneed());
+
+        unsafe {
+            let layout = Layout::new::<[u8; 16]>();
+            let one = allocator.alloc(layout);
+            one.write_bytes(0xAB, 16);
+            assert!(allocator.advise_dontneed());
+            assert!((0..16).all(|i| one.add(i).read() == 0xAB));
+
+            let rest = allocator.alloc(Layout::new::<[u8; 1024]>());
+            assert!((0..1024).all(|i| rest.add(i).read() == 0));
+        }
+    }
+
+    #[test]
+    fn offsets() {
+        let allocator = LinearAllocator::new();
+        let layout = Layout::new::<[u8; 16]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            let offset = allocator.offset_of(one).unwrap();
+            assert_eq!(allocator.ptr_of(offset), one);
+            assert_eq!(offset, ARENA_SIZE - 16);
+        }
+        assert_eq!(allocator.offset_of(ptr::null()), None);
+        assert!(allocator.ptr_of(ARENA_SIZE).is_null());
+    }
+
+    #[test]
+    fn alloc_at() {
+        let allocator = LinearAllocator::new();
+        let layout = Layout::new::<[u8; 16]>();
+        let base = allocator.as_ptr_range().start;
+
+        unsafe {
+            let placed = allocator.alloc_at(1024, layout);
+            assert_eq!(placed, base.add(1024));
+            // Above the new frontier
+            assert!(allocator.alloc_at(2048, layout).is_null());
+            assert!(allocator.alloc_at(1020, layout).is_null());
+
+            let below = allocator.alloc(layout);
+            assert!(below.add(16) <= placed);
+        }
+    }
+
+    #[test]
+    fn typed_arena() {
+        let mut arena = TypedArena::<u64>::new();
+
+        let values = (0..100u64)
+            .map(|i| arena.alloc(i * 31).unwrap() as *mut u64)
+            .collect::<Vec<_>>();
+        for (i, &value) in values.iter().enumerate() {
+            assert_eq!(unsafe { value.read() }, i as u64 * 31);
+        }
+
+        arena.reset();
+        let reused = arena.alloc(7).unwrap() as *mut u64;
+        assert_eq!(reused, values[0]);
+
+        let arena = TypedArena::<[u8; 1024]>::new();
+        assert!((0..4).all(|_| arena.alloc([0; 1024]).is_some()));
+        assert!(arena.alloc([0; 1024]).is_none());
+    }
+
+    #[test]
+    fn on_oom() {
+        static EXHAUSTED: AtomicBool = AtomicBool::new(false);
+        let allocator = LinearAllocator::new();
+        allocator.on_oom(|| EXHAUSTED.store(true, Ordering::Relaxed));
+
+        unsafe {
+            let too_aligned = Layout::from_size_align(8, MAX_SUPPORTED_ALIGN * 2).unwrap();
+            assert!(allocator.alloc(too_aligned).is_null());
+            assert!(!EXHAUSTED.load(Ordering::Relaxed));
+
+            let layout = Layout::new::<[u8; 1024]>();
+            while !allocator.alloc(layout).is_null() {}
+            assert!(EXHAUSTED.swap(false, Ordering::Relaxed));
+
+            // Only called once
+            assert!(allocator.alloc(layout).is_null());
+            assert!(!EXHAUSTED.load(Ordering::Relaxed));
+        }
+    }
+
+    #[test]
+    fn try_grow_in_place() {
+        let allocator = LinearAllocator::new();
+        let layout = Layout::new::<[u8; 16]>();
+
+        unsafe {
+            let top = allocator.alloc(layout);
+            assert!(allocator.try_grow_in_place(top, layout, 16));
+            assert!(!allocator.try_grow_in_place(top, layout, 32));
+            // The top block can still be grown by moving it
+            assert!(!allocator.realloc(top, layout, 32).is_null());
+        }
+    }
+
+    #[test]
+    fn bump_directions() {
+        for direction in [BumpDirection::Down, BumpDirection::Up] {
+            let allocator = LinearAllocator::with_direction(direction);
+            let base = allocator.as_ptr_range().start;
+
+            unsafe {
+                let mut previous = allocator.alloc(Layout::new::<u8>());
+                for align in [1, 8, 64, 256] {
+                    let ptr = allocator.alloc(Layout::from_size_align(24, align).unwrap());
+                    assert!(ptr.addr().is_multiple_of(align), "{direction:?}");
+                    match direction {
+                        BumpDirection::Down => assert!(ptr.add(24) <= previous),
+                        BumpDirection::Up => assert!(ptr >= previous.add(1)),
+                    }
+                    previous = ptr;
+                }
+
+                let layout = Layout::new::<[u8; 512]>();
+                while !allocator.alloc(layout).is_null() {}
+                assert!(allocator.used_bytes() > ARENA_SIZE - 512, "{direction:?}");
+                assert!(allocator.as_ptr_range().contains(&previous));
+
+                allocator.reset();
+                let first = allocator.alloc(layout);
+                match direction {
+                    BumpDirection::Down => assert!(first.add(512) <= base.add(ARENA_SIZE)),
+                    BumpDirection::Up => assert!(first >= base && first < base.add(64)),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn growable_top_realloc() {
+        let allocator = GrowableLinear::new();
+        let layout = Layout::from_size_align(4000, 8).unwrap();
+
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            (0..4000).for_each(|i| ptr.add(i).write(i as u8));
+            let end_of_first = allocator.as_ptr_range().end;
+
+            // Past a `LinearAllocator`'s whole arena, the frontier moves into freshly committed pages
+            assert_eq!(allocator.realloc(ptr, layout, 8000), ptr);
+            assert!((0..4000).all(|i| ptr.add(i).read() == i as u8));
+            assert_eq!(allocator.used_bytes(), 8000);
+            assert!(allocator.used_bytes() > LinearAllocator::CAPACITY);
+            assert!(allocator.as_ptr_range().end > end_of_first);
+            ptr.add(7999).write(1);
+
+            // Blocks below the top are copied up
+            let top = allocator.alloc(Layout::new::<u64>());
+            let moved = allocator.realloc(ptr, Layout::from_size_align(8000, 8).unwrap(), 9000);
+            assert!(moved > top);
+            assert!((0..4000).all(|i| moved.add(i).read() == i as u8));
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn bump_up_grow() {
+        let allocator = LinearAllocator::with_direction(BumpDirection::Up);
+        let layout = Layout::new::<[u8; 16]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            let top = allocator.alloc(layout);
+            assert!(!allocator.try_grow_in_place(one, layout, 32));
+            assert!(allocator.try_grow_in_place(top, layout, 32));
+
+            // The top block stays put and the next one is bumped past it
+            let grown = Layout::new::<[u8; 32]>();
+            assert_eq!(allocator.realloc(top, grown, 64), top);
+            assert_eq!(allocator.alloc(layout), top.add(64));
+            assert!(allocator.realloc(one, layout, ARENA_SIZE).is_null());
+        }
     }
 }
@@ -1,25 +1,67 @@
 use core::{
-    alloc::GlobalAlloc,
+    alloc::{GlobalAlloc, Layout},
     cell::UnsafeCell,
-    ffi::c_void,
-    fmt::Pointer,
-    ops::Deref,
+    fmt,
+    mem::MaybeUninit,
+    ops::{Deref, Range},
     ptr::{self, slice_from_raw_parts_mut},
-    sync::atomic::AtomicU8,
+    sync::atomic::{AtomicPtr, AtomicU8, AtomicU32, AtomicUsize, Ordering},
 };
 
-use libc::{
-    __errno_location, ENOMEM, MAP_ANONYMOUS, MAP_FAILED, MAP_FIXED, MAP_NORESERVE, MAP_PRIVATE,
-    MAP_SHARED, PROT_READ, PROT_WRITE, arpd_request,
+use crate::{
+    error::AllocFailure,
+    page_allocator::YerbaAlloc,
+    page_source::{LibcMmap, PageSource},
 };
 
-const PAGE_SIZE: usize = 4096;
 const MIN_BLOCK_SIZE: usize = 8;
 const MAX_ALIGN: usize = 32;
-const MIN_ALIGN: usize = 2;
+/// The arena is reserved up front so that it can grow contiguously
+const MAX_PAGES: usize = u8::MAX as usize;
+
+/// Size classes served by the small-allocation bins
+const BIN_SIZES: [usize; 3] = [MIN_BLOCK_SIZE, 16, 32];
+
+const USED_BIT: usize = 1 << (usize::BITS - 1);
+const BINNED_BIT: usize = 1 << (usize::BITS - 2);
+/// Set on free blocks whose data was filled with `FREE_PATTERN`
+const FILLED_BIT: usize = 1 << (usize::BITS - 3);
+/// Set on used blocks split into slots for tiny allocations
+const SLAB_BIT: usize = 1 << (usize::BITS - 4);
+/// Set on the block at the top of the committed arena, which ends the block list
+/// Any size is valid for a block, zero included, so the size can't mark the end
+const SENTINEL_BIT: usize = 1 << (usize::BITS - 5);
+const FLAG_BITS: usize = USED_BIT | BINNED_BIT | FILLED_BIT | SLAB_BIT | SENTINEL_BIT;
+
+/// Written over freed data when the `heap-check` feature is enabled
+const FREE_PATTERN: u8 = 0xDD;
+/// Number of searches the share of slow ones is measured over before `alloc` decides whether to coalesce
+const DEFRAG_WINDOW: usize = 64;
+/// Number of slots in a slab, one for every bit of its bitmap
+const SLAB_SLOTS: usize = u64::BITS as usize;
+
+/// Kept at the start of a slab's data, in front of its slots
+#[repr(C)]
+struct SlabMeta {
+    /// Bit `i` is set while slot `i` is allocated
+    taken: u64,
+    /// Size and alignment of every slot
+    granule: usize,
+}
+
+/// Kept in front of the data of each object mapped outside the arena, linking them into a list
+#[repr(C)]
+struct LargeHeader {
+    next: *mut LargeHeader,
+    prev: *mut LargeHeader,
+    /// Layout the object was allocated with, or last reallocated to
+    layout: Layout,
+}
 
 /// Represents a memory block
-/// The most significant bit of the offset is used to mark whether the block is used
+/// `size` is the number of bytes following the header, including the alignment offset
+/// The five most significant bits of the offset are used to mark whether the block is used, binned, filled, a slab
+/// or the sentinel
 /// Thus you should never access offset field directly, instead, use the provided API
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
@@ -28,12 +70,6 @@ struct Header {
     offset: usize,
 }
 
-impl Default for Header {
-    fn default() -> Self {
-        Header::new(PAGE_SIZE - size_of::<Header>(), 0)
-    }
-}
-
 impl Header {
     pub fn new(size: usize, offset: usize) -> Header {
         Header { size, offset }
@@ -54,15 +90,13 @@ impl HeaderPtr {
     }
 
     pub fn get_offset(&self) -> usize {
-        unsafe { (*self.0).offset & (0 as usize) << (size_of::<usize>() * 8 - 1) }
+        unsafe { (*self.0).offset & !FLAG_BITS }
     }
 
     pub fn set_offset(&mut self, offset: usize) {
-        let used: bool = self.used();
         unsafe {
-            (*self.0).offset = offset;
+            (*self.0).offset = ((*self.0).offset & FLAG_BITS) | offset;
         }
-        self.set_used(used);
     }
 
     pub fn used(&self) -> bool {
@@ -71,30 +105,83 @@ impl HeaderPtr {
     }
 
     fn set_used(&mut self, used: bool) {
+        self.set_flag(USED_BIT, used);
+    }
+
+    /// Whether the block is free but parked in one of the small-allocation bins
+    /// Binned blocks must not be merged or handed out by the general scan
+    pub fn binned(&self) -> bool {
+        unsafe { (*self.0).offset & BINNED_BIT != 0 }
+    }
+
+    fn set_binned(&mut self, binned: bool) {
+        self.set_flag(BINNED_BIT, binned);
+    }
+
+    /// Whether the block's data holds `FREE_PATTERN`, apart from a bin link
+    fn filled(&self) -> bool {
+        unsafe { (*self.0).offset & FILLED_BIT != 0 }
+    }
+
+    fn set_filled(&mut self, filled: bool) {
+        self.set_flag(FILLED_BIT, filled);
+    }
+
+    /// Whether the block is used as a slab, holding tiny allocations in slots after a `SlabMeta`
+    fn slab(&self) -> bool {
+        unsafe { (*self.0).offset & SLAB_BIT != 0 }
+    }
+
+    fn set_slab(&mut self, slab: bool) {
+        self.set_flag(SLAB_BIT, slab);
+    }
+
+    /// Whether the header ends the block list, nothing past it is walked
+    fn sentinel(&self) -> bool {
+        unsafe { (*self.0).offset & SENTINEL_BIT != 0 }
+    }
+
+    fn set_sentinel(&mut self, sentinel: bool) {
+        self.set_flag(SENTINEL_BIT, sentinel);
+    }
+
+    fn set_flag(&mut self, flag: usize, set: bool) {
         unsafe {
-            let k = size_of::<usize>() * 8 - 1;
-            (*self.0).offset &= 0 << k;
-            (*self.0).offset &= (used as usize) << k;
+            if set {
+                (*self.0).offset |= flag;
+            } else {
+                (*self.0).offset &= !flag;
+            }
         }
     }
 
     pub fn free(&mut self) {
+        self.set_slab(false);
         self.set_used(false)
     }
 
     pub fn mark_used(&mut self) {
+        self.set_filled(false);
         self.set_used(true)
     }
 
+    /// Merges the block right after this one into it, taking over the end of the list if `next` was the sentinel
+    /// The absorbed header is cleared
+    fn absorb(&mut self, next: &HeaderPtr) {
+        self.set_sentinel(next.sentinel());
+        self.add_size(next.size() + size_of::<Header>());
+        unsafe { next.write_bytes(0, 1) };
+    }
+
     pub fn size(&self) -> usize {
         unsafe { (*self.0).size }
     }
 
-    pub fn add_size(&self, size: usize) {
+    pub fn add_size(&mut self, size: usize) {
         unsafe { (*self.0).size += size }
     }
 
-    pub fn set_size(&self, size: usize) {
+    pub fn set_size(&mut self, size: usize) {
         unsafe { (*self.0).size = size }
     }
 
@@ -104,11 +191,20 @@ impl HeaderPtr {
 
     fn get_data(&self) -> *mut u8 {
         let offset = self.get_offset();
-        unsafe { self.add(1).byte_add(offset).cast::<u8>() as *mut u8 }
+        unsafe { self.add(1).byte_add(offset).cast::<u8>() }
     }
 
-    fn last_addr(&self) -> usize {
-        self.addr() + size_of::<Header>() + self.get_offset() + self.size()
+    /// Number of bytes the data can hold, the block's size less the alignment offset in front of the data
+    /// What `allocated_size` reports, which is often more than was asked for since sizes are rounded up
+    fn usable(&self) -> usize {
+        self.size() - self.get_offset()
+    }
+
+    /// Address just past the block's data, None if it would wrap around the address space
+    fn last_addr(&self) -> Option<usize> {
+        self.addr()
+            .checked_add(size_of::<Header>())?
+            .checked_add(self.size())
     }
 }
 
@@ -126,109 +222,237 @@ impl From<*mut Header> for HeaderPtr {
 }
 
 // Headers are inlined to the buffer
-// Only allocates a single arena and returns a null pointer for allocations past that
+// Reserves address space for the arena up front and grows into it a page at a time
 // Allows the arbitrary allocation, deallocation, and reallocation of any block
 // Will merge empty blocks when necessary to fit new allocations
-struct LinkedListAllocator {
+// Freed blocks of the smallest sizes are kept in bins so they can be reused without a scan
+// Pages come from a `PageSource`, the OS by default
+pub struct LinkedListAllocator<S: PageSource = LibcMmap> {
     buf: *mut UnsafeCell<[u8]>,
+    /// The OS page size, queried when the arena is created
+    page_size: usize,
     pages: AtomicU8,
+    bins: [AtomicPtr<Header>; BIN_SIZES.len()],
+    /// Blocks below this address are left alone while a checkpoint is active, 0 otherwise
+    floor: AtomicUsize,
+    /// Bit pattern of the `f32` usage ratio past which `alloc` commits a page ahead of demand
+    grow_threshold: AtomicU32,
+    /// Number of headers in the list, kept up to date wherever one is written or absorbed
+    blocks: AtomicUsize,
+    /// Searches that found a ready block, and searches that had to merge blocks or commit a page
+    fast_searches: AtomicUsize,
+    slow_searches: AtomicUsize,
+    /// Number of times `alloc` coalesced the arena because too many searches were slow
+    defrags: AtomicUsize,
+    /// Bit pattern of the `f32` share of slow searches past which `alloc` coalesces the arena
+    defrag_threshold: AtomicU32,
+    /// Searches and slow searches since the share was last checked
+    window_searches: AtomicUsize,
+    window_slow: AtomicUsize,
+    /// Allocations up to this size are packed into slabs, 0 if they aren't
+    slab_granule: AtomicUsize,
+    /// Allocations larger than this get a mapping of their own instead of a block in the arena
+    large_threshold: AtomicUsize,
+    /// Head of the list of objects mapped outside the arena
+    large_objects: AtomicPtr<LargeHeader>,
+    /// Maps the large objects
+    large: YerbaAlloc,
+    source: S,
+}
+
+/// How the searches for a free block have gone, since the allocator was created
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchStats {
+    /// Searches that found a block that already fit
+    pub fast: usize,
+    /// Searches that had to merge free blocks or commit a new page to fit
+    pub slow: usize,
+    /// Number of times too many slow searches made `alloc` coalesce the arena
+    pub defrags: usize,
+}
+
+/// A frontier in the arena, everything allocated past it can be released at once
+#[derive(Debug)]
+pub struct Checkpoint {
+    frontier: usize,
+    /// The floor of the enclosing checkpoint, restored along with this one
+    floor: usize,
+}
+
+/// Iterator over the used blocks of a `LinkedListAllocator`, returned by `drain`
+/// The whole arena is freed when it's dropped, whether or not every block was visited
+pub struct Drain<'a, S: PageSource = LibcMmap> {
+    allocator: &'a LinkedListAllocator<S>,
+    block: HeaderPtr,
+    /// The slab being drained and its taken slots that haven't been yielded yet
+    slab: HeaderPtr,
+    slots: u64,
+    /// The next large object, visited once the arena is done
+    large: *mut LargeHeader,
+}
+
+impl<S: PageSource> Iterator for Drain<'_, S> {
+    type Item = *mut u8;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.slots != 0 {
+                let slot = self.slots.trailing_zeros() as usize;
+                self.slots &= self.slots - 1;
+                return Some(LinkedListAllocator::<S>::slot_ptr(&self.slab, slot));
+            }
+            if self.block.is_null() {
+                let large = self.large;
+                if large.is_null() {
+                    return None;
+                }
+                self.large = unsafe { (*large).next };
+                return Some(LinkedListAllocator::<S>::large_data(large));
+            }
+
+            let block = HeaderPtr::new(self.block.0);
+            self.block.set(&self.allocator.next_header(&block));
+            if block.used() && block.slab() {
+                self.slots = unsafe { (*LinkedListAllocator::<S>::slab_meta(&block)).taken };
+                self.slab = block;
+            } else if block.used() {
+                return Some(block.get_data());
+            }
+        }
+    }
+}
+
+impl<S: PageSource> Drop for Drain<'_, S> {
+    fn drop(&mut self) {
+        self.allocator.clear();
+    }
 }
 
 impl LinkedListAllocator {
+    /// Largest alignment `alloc` will satisfy, larger requests return null
+    pub const MAX_ALIGN: usize = MAX_ALIGN;
+
+    /// Reserves the arena wherever the kernel chooses to place it
+    /// Never maps over an existing region, so it can live alongside the normal heap
+    /// Only the first page is accessible, the rest is committed by `request_new_page`
     pub fn new() -> Self {
-        const {
-            let header_size = size_of::<Header>();
-            assert!(header_size < PAGE_SIZE);
-            assert!(header_size % 8 == 0)
+        Self::with_source(LibcMmap)
+    }
+
+    /// Like `new`, but reports a failure to reserve or commit the arena instead of panicking
+    pub fn try_new() -> Result<Self, AllocFailure> {
+        Self::try_with_source(LibcMmap)
+    }
+}
+
+impl<S: PageSource> LinkedListAllocator<S> {
+    /// Allocates zeroed memory for `count` elements of `size` bytes, like C's `calloc`
+    /// Returns null if the total size is zero or overflows
+    pub fn calloc(&self, count: usize, size: usize) -> *mut u8 {
+        match crate::calloc_layout(count, size) {
+            Some(layout) => unsafe { self.alloc_zeroed(layout) },
+            None => ptr::null_mut(),
+        }
+    }
+
+    /// Allocates room for a `T`, left uninitialized, null if it doesn't fit
+    /// Free it with `dealloc` and `Layout::new::<T>()`
+    pub fn alloc_uninit<T>(&self) -> *mut MaybeUninit<T> {
+        unsafe { self.alloc(Layout::new::<T>()) }.cast()
+    }
+
+    /// Allocates room for `len` values of `T`, left uninitialized, null if it doesn't fit or the size overflows
+    /// Free it with `dealloc` and `Layout::array::<T>(len)`
+    pub fn alloc_uninit_slice<T>(&self, len: usize) -> *mut [MaybeUninit<T>] {
+        let ptr = match Layout::array::<T>(len) {
+            Ok(layout) => unsafe { self.alloc(layout) },
+            Err(_) => ptr::null_mut(),
+        };
+        ptr::slice_from_raw_parts_mut(ptr.cast(), len)
+    }
+
+    /// Reserves the arena from `source` and backs its first page
+    /// Panics if either fails, see `try_with_source`
+    pub fn with_source(source: S) -> Self {
+        match Self::try_with_source(source) {
+            Ok(allocator) => allocator,
+            Err(failure) => panic!("Failed to set up the arena: {failure}"),
         }
-        let head = Header::default();
+    }
+
+    /// Reserves the arena from `source` and backs its first page, reporting a failure instead of panicking
+    pub fn try_with_source(source: S) -> Result<Self, AllocFailure> {
+        const { assert!(size_of::<Header>().is_multiple_of(8)) }
+        let page_size = *crate::page_allocator::PAGE_SIZE;
+        assert!(page_size > size_of::<Header>());
+        let arena_len = page_size * MAX_PAGES;
 
         unsafe {
-            let mem_ptr = libc::mmap(
-                ptr::null_mut(),
-                PAGE_SIZE,
-                PROT_READ | PROT_WRITE,
-                MAP_ANONYMOUS | MAP_PRIVATE | MAP_FIXED,
-                -1,
-                0,
-            );
-            if mem_ptr == MAP_FAILED {
-                panic!("Failed to increment program break");
+            let mem_ptr = source.reserve(arena_len);
+            if mem_ptr.is_null() {
+                return Err(AllocFailure::ReserveFailed);
+            }
+            // Every address in the arena is computed from its base, so its end has to be representable
+            if mem_ptr.addr().checked_add(arena_len).is_none() {
+                source.release(mem_ptr, arena_len);
+                return Err(AllocFailure::ReserveFailed);
+            }
+            if source.map(mem_ptr, page_size).is_null() {
+                source.release(mem_ptr, arena_len);
+                return Err(AllocFailure::CommitFailed);
             }
 
-            let buf =
-                slice_from_raw_parts_mut(mem_ptr as *mut u8, PAGE_SIZE) as *mut UnsafeCell<[u8]>;
-            buf.cast::<Header>().write(head);
+            let buf = slice_from_raw_parts_mut(mem_ptr, arena_len) as *mut UnsafeCell<[u8]>;
+            let mut first = HeaderPtr::new(buf);
+            first.write(Header::new(page_size - size_of::<Header>(), 0));
+            first.set_sentinel(true);
 
-            Self {
+            Ok(Self {
                 buf,
+                page_size,
                 pages: AtomicU8::new(1),
-            }
+                bins: [const { AtomicPtr::new(ptr::null_mut()) }; BIN_SIZES.len()],
+                floor: AtomicUsize::new(0),
+                grow_threshold: AtomicU32::new(1.0f32.to_bits()),
+                blocks: AtomicUsize::new(1),
+                fast_searches: AtomicUsize::new(0),
+                slow_searches: AtomicUsize::new(0),
+                defrags: AtomicUsize::new(0),
+                defrag_threshold: AtomicU32::new(1.0f32.to_bits()),
+                window_searches: AtomicUsize::new(0),
+                window_slow: AtomicUsize::new(0),
+                slab_granule: AtomicUsize::new(0),
+                large_threshold: AtomicUsize::new(usize::MAX),
+                large_objects: AtomicPtr::new(ptr::null_mut()),
+                large: YerbaAlloc::new(),
+                source,
+            })
         }
     }
 
+    /// Gets the block after `header_ptr`, null if it's the sentinel at the top of the arena
+    /// A corrupted size that would wrap around the address space ends the walk too
     fn next_header(&self, header_ptr: &HeaderPtr) -> HeaderPtr {
-        if header_ptr.size() == 0 {
-            unsafe {
-                header_ptr.write_bytes(0, 1);
-            }
-            return HeaderPtr::null();
-        }
-        if header_ptr.last_addr() > self.last_addr() {
+        if header_ptr.sentinel() || header_ptr.last_addr().is_none() {
             return HeaderPtr::null();
         }
         unsafe {
             header_ptr
-                .byte_add(header_ptr.get_offset() + header_ptr.size())
+                .byte_add(size_of::<Header>() + header_ptr.size())
                 .into()
         }
     }
 
-    // fn next_empty_block(&self, header_ptr: &HeaderPtr) -> HeaderPtr {
-    //     if header_ptr.size() == 0 {
-    //         unsafe {
-    //             header_ptr.write_bytes(0, 1);
-    //         }
-    //         return HeaderPtr::null();
-    //     }
-    //     if header_ptr.get_offset() + header_ptr.size() + header_ptr.addr()
-    //         > self.buf_ptr().addr() + PAGE_SIZE
-    //     {
-    //         return HeaderPtr::null();
-    //     }
-    //     unsafe {
-    //         let mut next: HeaderPtr = header_ptr
-    //             .byte_add(header_ptr.get_offset() + header_ptr.size())
-    //             .into();
-    //         if next.is_null() {
-    //             return HeaderPtr::null();
-    //         }
-    //         if next.used() {
-    //             next.set(&self.next_empty_block(&next));
-    //         }
-    //
-    //         next
-    //     }
-    // }
-
     /// Gets the next block in the array, even if it's not initialized
     /// Returns null if out of owned range
     fn next_header_unchecked(&self, header_ptr: &HeaderPtr) -> HeaderPtr {
-        let pages = self.pages.load(std::sync::atomic::Ordering::Relaxed) as usize;
-        println!(
-            "{pages} {} {} {}",
-            header_ptr.addr(),
-            self.buf_ptr().addr(),
-            header_ptr.size()
-        );
-        let t = header_ptr.last_addr() - self.last_addr();
-        println!("{t}");
-        if header_ptr.last_addr() > self.last_addr() {
-            return HeaderPtr::null();
+        match header_ptr.last_addr() {
+            Some(end) if end <= self.last_addr() => {}
+            _ => return HeaderPtr::null(),
         }
         unsafe {
             header_ptr
-                .byte_add(size_of::<Header>() + header_ptr.get_offset() + header_ptr.size())
+                .byte_add(size_of::<Header>() + header_ptr.size())
                 .into()
         }
     }
@@ -236,25 +460,60 @@ impl LinkedListAllocator {
     fn find_empty_block(&self, size: usize, align: usize) -> HeaderPtr {
         let mut last_header_ptr = HeaderPtr::null();
         let mut header_ptr = self.first_block();
+        let mut slow = false;
 
         while !header_ptr.is_null() {
             unsafe {
-                if header_ptr.used() {
+                if self.unavailable(&header_ptr) {
+                    let next_block = self.next_header(&header_ptr);
+                    if next_block.is_null() {
+                        // The top of the arena is taken, start a fresh block in a new page
+                        let top = self.buf_ptr().add(self.last_addr() - self.buf_ptr().addr());
+                        if self.request_new_page().is_err() {
+                            return HeaderPtr::null();
+                        }
+
+                        slow = true;
+
+                        let mut top_header_ptr = HeaderPtr::new(top);
+                        top_header_ptr.write(Header::new(self.page_size - size_of::<Header>(), 0));
+                        top_header_ptr.set_sentinel(true);
+                        header_ptr.set_sentinel(false);
+                        self.blocks.fetch_add(1, Ordering::Relaxed);
+                        last_header_ptr.set(&header_ptr);
+                        header_ptr.set(&top_header_ptr);
+
+                        continue;
+                    }
+
                     last_header_ptr.set(&header_ptr);
-                    let next_block = &self.next_header(&header_ptr);
-                    header_ptr.set(next_block);
+                    header_ptr.set(&next_block);
 
                     continue;
                 }
+                Self::check_free_pattern(&header_ptr);
+
+                // Coalesce with the previous block first, it can only make the fit easier
+                let mergeable = !last_header_ptr.is_null() && !self.unavailable(&last_header_ptr);
+                if mergeable {
+                    slow = true;
+                    last_header_ptr.set_filled(false);
+                    last_header_ptr.absorb(&header_ptr);
+                    self.blocks.fetch_sub(1, Ordering::Relaxed);
+                    header_ptr.set(&last_header_ptr);
+                    last_header_ptr = HeaderPtr::null();
+                }
 
                 // We don't actually use this pointer again, it's just for calculating the offset
                 let data_ptr = header_ptr.add(1).cast::<u8>();
-                let alignment_offset = data_ptr.align_offset(align);
+                let alignment_offset = crate::padding_for(data_ptr, align);
                 if alignment_offset == usize::MAX {
                     return HeaderPtr::null();
                 }
 
-                let required_size = size + alignment_offset;
+                let Some(required_size) = size.checked_add(alignment_offset) else {
+                    return HeaderPtr::null();
+                };
                 let fits = header_ptr.size() >= required_size;
 
                 // We've found a block that fits
@@ -264,308 +523,1337 @@ impl LinkedListAllocator {
                     break;
                 }
 
-                // We've found a pair of free blocks that can be merged to fit
-                let mergeable = !last_header_ptr.is_null() && !last_header_ptr.used();
-                if mergeable {
-                    let merged_size = header_ptr.size() + last_header_ptr.size();
-                    let fits_with_merge = merged_size >= required_size;
-
-                    if fits_with_merge {
-                        let data_ptr = last_header_ptr.add(1);
-                        let alignment_offset = data_ptr.align_offset(align);
-                        if alignment_offset == usize::MAX {
-                            return HeaderPtr::null();
-                        }
-
-                        last_header_ptr.set_offset(alignment_offset);
-                        last_header_ptr.add_size(
-                            header_ptr.size() + header_ptr.get_offset() + size_of::<Header>(),
-                        );
-
-                        header_ptr.write_bytes(0, size_of::<Header>());
-                        header_ptr = last_header_ptr;
-
-                        break;
+                let next_block = self.next_header(&header_ptr);
+                if next_block.is_null() {
+                    // The free block is on top of the arena, so it can just absorb a new page
+                    if self.request_new_page().is_err() {
+                        return HeaderPtr::null();
                     }
+                    header_ptr.add_size(self.page_size);
+                    slow = true;
+
+                    continue;
                 }
 
                 last_header_ptr.set(&header_ptr);
-                let next_block = &self.next_header(&header_ptr);
-                println!("next_block {}", next_block.addr());
-                if next_block.is_null() {
-                    self.request_new_page();
-
-                    let remaining_size = self.last_addr()
-                        - size_of::<Header>() * 2
-                        - alignment_offset
-                        - size
-                        - self.buf_ptr().addr();
-                    println!("{remaining_size}");
-
-                    let header = Header::new(size, alignment_offset);
-                    let header_ptr =
-                        HeaderPtr::new(slice_from_raw_parts_mut(last_header_ptr.0, size));
-                    header_ptr.write(header);
-
-                    let new_top_header = Header::new(remaining_size, 0);
-                    let top_header_ptr = self.next_header_unchecked(&header_ptr);
-                    println!("header: {}", top_header_ptr.addr());
-                    top_header_ptr.write(new_top_header)
-                }
-                header_ptr.set(next_block);
-                println!("Here")
+                header_ptr.set(&next_block);
             }
         }
 
+        let searches = if slow {
+            &self.slow_searches
+        } else {
+            &self.fast_searches
+        };
+        searches.fetch_add(1, Ordering::Relaxed);
+        self.window_searches.fetch_add(1, Ordering::Relaxed);
+        self.window_slow.fetch_add(slow as usize, Ordering::Relaxed);
+
         header_ptr
     }
 
-    fn first_block(&self) -> HeaderPtr {
-        HeaderPtr(self.buf_ptr() as *mut Header)
+    /// Whether the scan has to skip the block, either because it's taken or it's below an active checkpoint
+    fn unavailable(&self, header_ptr: &HeaderPtr) -> bool {
+        header_ptr.used()
+            || header_ptr.binned()
+            || header_ptr.addr() < self.floor.load(Ordering::Relaxed)
     }
 
-    fn last_addr(&self) -> usize {
-        let pages = self.pages.load(std::sync::atomic::Ordering::Relaxed) as usize;
-        unsafe { self.buf_ptr().add(PAGE_SIZE * pages).addr() }
-    }
+    /// Records the end of the highest block in use
+    /// Until the checkpoint is restored, new allocations are only placed past it
+    pub fn checkpoint(&self) -> Checkpoint {
+        let mut frontier = self.buf_ptr().addr();
+        let mut block = self.first_block();
+        while !block.is_null() {
+            if (block.used() || block.binned())
+                && let Some(end) = block.last_addr()
+            {
+                frontier = end;
+            }
+            block.set(&self.next_header(&block));
+        }
 
-    fn buf_ptr(&self) -> *mut u8 {
-        unsafe { (*self.buf).get().cast() }
+        let floor = self.floor.load(Ordering::Relaxed);
+        self.floor.store(frontier.max(floor), Ordering::Relaxed);
+
+        Checkpoint { frontier, floor }
     }
 
-    /// Finds the block representing the given data pointer
-    fn find_ptr_block(&self, ptr: *mut u8) -> HeaderPtr {
+    /// Frees every block allocated since `checkpoint` was taken
+    /// Pointers to those blocks must not be used afterwards
+    pub fn restore(&self, checkpoint: Checkpoint) {
         let mut block = self.first_block();
-        while block.get_data() != ptr && !block.is_null() {
+        while !block.is_null() {
+            if block.used() && block.addr() >= checkpoint.frontier {
+                unsafe { crate::zeroize(block.add(1).cast(), block.size()) };
+                block.free();
+                block.set_offset(0);
+            }
             block.set(&self.next_header(&block));
         }
 
-        block
+        self.floor.store(checkpoint.floor, Ordering::Relaxed);
     }
 
-    fn number_of_blocks(&self) -> usize {
-        let mut c = 0;
-        let mut head = self.first_block();
-        while !head.is_null() {
-            c += 1;
-            head.set(&self.next_header(&head));
+    /// Yields the data pointer of every used block, slab slot and large object, then frees the whole arena when the iterator is dropped
+    /// Lets the caller run destructors over an arena of objects before its memory is reused
+    /// Nothing may be allocated or freed until the iterator is dropped, and the yielded pointers must not be used after
+    pub fn drain(&self) -> Drain<'_, S> {
+        Drain {
+            allocator: self,
+            block: self.first_block(),
+            slab: HeaderPtr::null(),
+            slots: 0,
+            large: self.large_objects.load(Ordering::Relaxed),
         }
+    }
 
-        c
+    /// Frees every block at once, leaving a single free block over the committed arena
+    /// Bins are emptied, large objects unmapped and any checkpoint is dropped
+    fn clear(&self) {
+        self.free_large_objects();
+        self.bins
+            .iter()
+            .for_each(|bin| bin.store(ptr::null_mut(), Ordering::Relaxed));
+        self.floor.store(0, Ordering::Relaxed);
+
+        let len = self.last_addr() - self.buf_ptr().addr() - size_of::<Header>();
+        let mut first = self.first_block();
+        unsafe {
+            crate::zeroize(first.add(1).cast(), len);
+            first.write(Header::new(len, 0));
+        }
+        first.set_sentinel(true);
+        self.blocks.store(1, Ordering::Relaxed);
+        Self::fill_free_pattern(&mut first);
     }
 
-    // Allocates a new page in memory and then returns the new top HeaderPtr
-    // with provenance of PAGE_SIZE
-    fn request_new_page(&self) {
-        let base_virtual_address = unsafe {
-            libc::mmap(
-                ptr::null_mut(),
-                PAGE_SIZE * 10,
-                PROT_READ | PROT_WRITE,
-                MAP_NORESERVE | MAP_ANONYMOUS | MAP_SHARED,
-                -1,
-                0,
-            )
-        };
-        let prog_brk = unsafe {
-            libc::mmap(
-                base_virtual_address,
-                PAGE_SIZE,
-                PROT_READ | PROT_WRITE,
-                MAP_ANONYMOUS | MAP_PRIVATE | MAP_FIXED,
-                -1,
-                0,
-            )
-        };
-        if prog_brk == MAP_FAILED {
-            panic!("Failed to allocate new page");
+    /// Merges the free blocks at the top of the arena and returns the whole pages they cover to the OS
+    /// The address space stays reserved, so the arena grows back into it on demand
+    pub fn reclaim(&self) {
+        let mut run = self.coalesce();
+        if run.is_null() {
+            return;
         }
-        assert_eq!(prog_brk, base_virtual_address);
 
-        let _ = self
-            .pages
-            .fetch_add(1, core::sync::atomic::Ordering::Relaxed);
-    }
+        let base = self.buf_ptr().addr();
+        let pages = self.pages.load(Ordering::Relaxed) as usize;
+        // The header of the run has to stay in a committed page
+        let kept_pages = (run.addr() + size_of::<Header>() - base).div_ceil(self.page_size);
+        if kept_pages >= pages {
+            return;
+        }
 
-    fn free_allocator(self) {
-        let pages = self.pages.load(core::sync::atomic::Ordering::Relaxed) as usize;
         unsafe {
-            self.buf.cast::<u8>().write_bytes(0, PAGE_SIZE * pages);
-            libc::munmap(self.buf.cast::<c_void>(), PAGE_SIZE * pages);
-            // libc::brk(self.buf.cast::<c_void>()); // .byte_sub(PAGE_SIZE * pages).cast::<c_void>()
-            // if *__errno_location() == ENOMEM {
-            //     panic!("Failed to increment program break");
-            // }
-        };
+            let start = self.buf_ptr().add(self.page_size * kept_pages);
+            self.source
+                .unmap(start, self.page_size * (pages - kept_pages));
+        }
+
+        run.set_size(base + self.page_size * kept_pages - run.addr() - size_of::<Header>());
+        self.pages.store(kept_pages as u8, Ordering::Relaxed);
     }
-}
 
-unsafe impl GlobalAlloc for LinkedListAllocator {
-    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        let size = layout.size();
-        let align = layout.align();
-        if align > MAX_ALIGN {
-            return ptr::null_mut();
+    /// Merges every run of free blocks into one, returning the run at the top of the arena if it's free
+    fn coalesce(&self) -> HeaderPtr {
+        let mut run = HeaderPtr::null();
+        let mut block = self.first_block();
+        while !block.is_null() {
+            let next = self.next_header(&block);
+            if self.unavailable(&block) {
+                run = HeaderPtr::null();
+            } else if run.is_null() {
+                run.set(&block);
+            } else {
+                run.set_filled(false);
+                run.absorb(&block);
+                self.blocks.fetch_sub(1, Ordering::Relaxed);
+            }
+            block.set(&next);
         }
 
-        let mut block = self.find_empty_block(size, align);
-        if block.is_null() {
+        run
+    }
+
+    /// The committed part of the arena, from its base to the top of the last page
+    /// Grows as pages are committed and shrinks when they're reclaimed
+    pub fn as_ptr_range(&self) -> Range<*mut u8> {
+        let start = self.buf_ptr();
+        start..start.with_addr(self.last_addr())
+    }
+
+    /// Offset of `ptr` from the start of the arena, None if it lies outside the committed part
+    /// Offsets stay valid when the arena's contents are saved and loaded somewhere else, unlike pointers
+    pub fn offset_of(&self, ptr: *const u8) -> Option<usize> {
+        let range = self.as_ptr_range();
+        range
+            .contains(&ptr.cast_mut())
+            .then(|| ptr.addr() - range.start.addr())
+    }
+
+    /// Pointer `offset` bytes into the arena, the inverse of `offset_of`, null if it lies outside the committed part
+    pub fn ptr_of(&self, offset: usize) -> *mut u8 {
+        let range = self.as_ptr_range();
+        if offset >= range.end.addr() - range.start.addr() {
             return ptr::null_mut();
         }
-        let data_ptr = block.get_data();
 
-        let end_of_block = data_ptr.addr() + size;
-        let top_of_buf = self.last_addr();
-        if end_of_block > top_of_buf {
-            return ptr::null_mut();
+        unsafe { range.start.add(offset) }
+    }
+
+    /// Total size of the blocks in use, including any padding and slack inside them
+    pub fn used_bytes(&self) -> usize {
+        let mut used = 0;
+        let mut block = self.first_block();
+        while !block.is_null() {
+            if block.used() {
+                used += block.size();
+            }
+            block.set(&self.next_header(&block));
         }
 
-        block.mark_used();
+        used
+    }
 
-        let block_next_ptr = self.next_header_unchecked(&block);
+    /// Runs `f` and panics if it left more bytes in use than there were before, reporting how many
+    /// Meant for tests, to check a piece of code frees everything it allocates from this allocator
+    #[track_caller]
+    pub fn leak_check<F: FnOnce()>(&self, f: F) {
+        let baseline = self.used_bytes();
+        f();
+        let leaked = self.used_bytes().saturating_sub(baseline);
+        assert!(leaked == 0, "leaked {leaked} bytes");
+    }
 
-        if block.size() > size_of::<Header>() + size
-            && (block_next_ptr.addr() + size_of::<Header>()) < self.buf_ptr().addr() + PAGE_SIZE
-        {
-            let new_block_size = block.size() - size_of::<Header>() - size;
-            block.set_size(size);
-            let new_block = Header {
-                size: new_block_size,
-                offset: 0,
-            };
+    /// Walks the block list and checks its invariants, reporting the first one that's broken
+    /// Every block must lie within the committed arena, be the sentinel exactly if it's the block at the very top,
+    /// keep its offset inside its data and not be both used and binned,
+    /// and together the blocks must cover the committed arena exactly
+    /// Meant for development, to catch corrupted headers close to where they were corrupted
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let start = self.buf_ptr().addr();
+        let end = self.last_addr();
+        // Every block takes at least a header, so a longer walk must have gone round in circles
+        let max_blocks = (end - start) / size_of::<Header>();
+
+        let mut addr = start;
+        let mut blocks = 0;
+        while addr < end {
+            blocks += 1;
+            if blocks > max_blocks {
+                return Err("the block list loops");
+            }
+            if end - addr < size_of::<Header>() {
+                return Err("a block's header runs past the end of the committed arena");
+            }
 
-            unsafe {
-                block_next_ptr.write(new_block);
+            let block = HeaderPtr::new(self.buf_ptr().with_addr(addr));
+            let last = match block.last_addr() {
+                Some(last) if last <= end => last,
+                _ => return Err("a block extends past the end of the committed arena"),
+            };
+            if block.sentinel() && last != end {
+                return Err("the sentinel sits before the end of the arena");
+            }
+            if !block.sentinel() && last == end {
+                return Err("the block at the top of the arena isn't the sentinel");
+            }
+            if block.get_offset() > block.size() {
+                return Err("a block's alignment offset is larger than the block");
+            }
+            if block.used() && block.binned() {
+                return Err("a block is both used and binned");
+            }
+            if block.slab() && !block.used() {
+                return Err("a block is a slab but isn't used");
             }
+
+            addr += size_of::<Header>() + block.size();
+        }
+        if blocks != self.number_of_blocks() {
+            return Err("the cached block count doesn't match the list");
         }
 
-        data_ptr
+        Ok(())
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: core::alloc::Layout) {
-        let mut block = self.find_ptr_block(ptr);
+    /// Number of bytes usable at `ptr`, which may be more than were asked for
+    /// None if `ptr` isn't the start of a block from this allocator
+    pub fn allocated_size(&self, ptr: *mut u8) -> Option<usize> {
+        if !self.owns(ptr) {
+            let large = self.find_large(ptr);
+            return (!large.is_null()).then(|| unsafe { (*large).layout.size() });
+        }
+        let block = self.find_ptr_block(ptr);
+        if block.is_null() {
+            return self
+                .find_slot(ptr)
+                .map(|(slab, _)| unsafe { (*Self::slab_meta(&slab)).granule });
+        }
+        if !block.used() {
+            return None;
+        }
 
-        block.free();
-        block.set_offset(0);
+        Some(block.usable())
     }
 
-    unsafe fn realloc(
+    /// Like `realloc`, but also returns the number of bytes usable at the new pointer
+    /// Collections can grow into the extra space without reallocating again
+    /// Returns a null pointer and 0 if the reallocation failed
+    ///
+    /// # Safety
+    /// Same as `GlobalAlloc::realloc`
+    #[track_caller]
+    pub unsafe fn realloc_usable(
         &self,
         ptr: *mut u8,
         layout: core::alloc::Layout,
         new_size: usize,
-    ) -> *mut u8 {
-        // First look forward for adjacent free blocks
-        let mut header_ptr = self.find_ptr_block(ptr);
-        header_ptr.free();
-        let mut frontier = self.next_header(&header_ptr);
-        let mut acc_size = header_ptr.size();
-        while acc_size < new_size && !frontier.is_null() {
-            if frontier.used() {
-                break;
-            }
+    ) -> (*mut u8, usize) {
+        let new_ptr = unsafe { self.realloc(ptr, layout, new_size) };
+        match self.allocated_size(new_ptr) {
+            Some(usable) => (new_ptr, usable),
+            None => (ptr::null_mut(), 0),
+        }
+    }
 
-            acc_size += frontier.size() + frontier.get_offset() + size_of::<Header>();
+    /// Like `realloc`, but also returns whether the block moved, false if the reallocation failed
+    /// Merging with the free block in front moves the data back, which counts as moving
+    ///
+    /// # Safety
+    /// Same as `GlobalAlloc::realloc`
+    #[track_caller]
+    pub unsafe fn realloc_tracked(
+        &self,
+        ptr: *mut u8,
+        layout: core::alloc::Layout,
+        new_size: usize,
+    ) -> (*mut u8, bool) {
+        let new_ptr = unsafe { self.realloc(ptr, layout, new_size) };
+        (new_ptr, !new_ptr.is_null() && new_ptr != ptr)
+    }
 
-            if acc_size >= new_size {
-                let alignment_offset = header_ptr.align_offset(layout.align());
-                unsafe {
-                    header_ptr.set_offset(alignment_offset);
-                    return header_ptr.get_data().add(alignment_offset);
-                }
-            }
-            unsafe { frontier.set(&HeaderPtr(frontier.add(1))) };
+    /// Grows the block at `ptr` to hold `new_size` bytes without moving it, merging the free blocks that follow
+    /// Returns false, leaving the block untouched, if a used block is in the way or the arena can't grow far enough
+    /// The caller then has to allocate and copy itself
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by this allocator with `layout`
+    pub unsafe fn try_grow_in_place(
+        &self,
+        ptr: *mut u8,
+        _layout: core::alloc::Layout,
+        new_size: usize,
+    ) -> bool {
+        let mut block = self.find_ptr_block(ptr);
+        if block.is_null() {
+            return false;
         }
-        if acc_size > new_size {
-            return ptr;
+        if block.usable() >= new_size {
+            return true;
         }
-        // Then start at the first block and check for available adjacent blocks again
-        let mut anchor = self.first_block();
-        while !anchor.is_null() {
-            if anchor.used() {
-                anchor.set(&self.next_header(&anchor));
-                continue;
-            }
-
-            acc_size = anchor.size();
-            frontier.set(&anchor);
-            while acc_size < new_size && !frontier.is_null() {
-                if frontier.used() {
-                    anchor.set(&self.next_header(&frontier));
-                    assert!(!anchor.is_null());
-                    break;
-                }
 
-                acc_size += frontier.size() + frontier.get_offset() + size_of::<Header>();
+        self.grow_in_place(&mut block, new_size, 1, None)
+    }
 
-                if acc_size >= new_size {
-                    let alignment_offset = header_ptr.align_offset(layout.align());
-                    unsafe {
-                        header_ptr.set_offset(alignment_offset);
-                        return header_ptr.get_data().add(alignment_offset);
-                    }
-                }
-                unsafe { frontier.set(&HeaderPtr(frontier.add(1))) };
-            }
+    /// Allocates the block whose data starts `offset` bytes into the arena
+    /// Returns null if that address isn't aligned for `layout` or the space around it isn't free
+    /// Lets a layout be rebuilt deterministically, such as one read back from disk
+    ///
+    /// # Safety
+    /// Same as `GlobalAlloc::alloc`
+    pub unsafe fn alloc_at(&self, offset: usize, layout: core::alloc::Layout) -> *mut u8 {
+        let size = layout.size().next_multiple_of(align_of::<Header>());
+        let align = crate::checked_align(layout.align()).max(align_of::<Header>());
+        let base = self.buf_ptr().addr();
+        let data = base + offset;
+        let Some(end) = data.checked_add(size) else {
+            return ptr::null_mut();
+        };
+        if offset < size_of::<Header>()
+            || !data.is_multiple_of(align)
+            || end > base + self.arena_len()
+        {
+            return ptr::null_mut();
         }
 
-        self.request_new_page();
-        let header_ptr = frontier;
-        // Ideally they don't request more than a page
-        while new_size > header_ptr.size() {
-            self.request_new_page();
-            unsafe { header_ptr.write_bytes(0, size_of::<Header>()) };
+        // The block holding the address, or the top block if it's past the committed pages
+        let mut block = self.first_block();
+        while block.last_addr().is_some_and(|last| last <= data) {
+            let next = self.next_header(&block);
+            if next.is_null() {
+                break;
+            }
+            block.set(&next);
         }
-
-        let data_ptr = header_ptr.get_data();
-        let alignment_offset = data_ptr.align_offset(layout.align());
-        let data_ptr = unsafe { data_ptr.add(alignment_offset) };
-
-        if new_size + alignment_offset > header_ptr.size() {
+        if self.unavailable(&block) || block.addr() + size_of::<Header>() > data {
             return ptr::null_mut();
         }
+        Self::check_free_pattern(&block);
+
+        // Absorb the free blocks after it until the data fits, they stay free if it doesn't
+        while block.last_addr().is_some_and(|last| last < end) {
+            let next = self.next_header(&block);
+            if next.is_null() {
+                if self.request_new_page().is_err() {
+                    return ptr::null_mut();
+                }
+                block.add_size(self.page_size);
 
-        return data_ptr;
-    }
-
-    unsafe fn alloc_zeroed(&self, layout: core::alloc::Layout) -> *mut u8 {
-        let size = layout.size();
-        unsafe {
-            let ptr = self.alloc(layout);
-            if ptr.is_null() {
+                continue;
+            }
+            if self.unavailable(&next) {
                 return ptr::null_mut();
             }
+            Self::check_free_pattern(&next);
 
-            ptr.write_bytes(0, size);
-            ptr
+            block.set_filled(false);
+            block.absorb(&next);
+            self.blocks.fetch_sub(1, Ordering::Relaxed);
         }
-    }
-}
 
-#[cfg(test)]
-mod test {
-    use core::alloc::Layout;
+        let mut gap = data - block.addr() - size_of::<Header>();
+        if gap >= size_of::<Header>() + MIN_BLOCK_SIZE {
+            // The space in front is large enough to stay free as a block of its own
+            let size = block.size();
+            let sentinel = block.sentinel();
+            block.set_sentinel(false);
+            block.set_size(gap - size_of::<Header>());
+            let mut front = self.next_header_unchecked(&block);
+            unsafe { front.write(Header::new(size - gap, 0)) };
+            front.set_sentinel(sentinel);
+            self.blocks.fetch_add(1, Ordering::Relaxed);
+            block.set(&front);
+            gap = 0;
+        }
 
-    use super::*;
+        block.set_offset(gap);
+        block.mark_used();
+        self.split_block(&mut block, gap + size);
 
-    #[test]
-    fn alloc_chunks() {
-        let allocator = LinkedListAllocator::new();
-        let layout = Layout::new::<[u8; 16]>();
+        block.get_data()
+    }
 
-        unsafe {
-            let chunk = allocator.alloc(layout);
-            assert!(!chunk.is_null());
-            allocator.dealloc(chunk, layout);
+    /// Whether `ptr` lies within the arena's reservation, committed or not
+    pub(crate) fn owns(&self, ptr: *const u8) -> bool {
+        ptr.addr()
+            .checked_sub(self.buf_ptr().addr())
+            .is_some_and(|offset| offset < self.arena_len())
+    }
 
-            let one = allocator.alloc(layout);
-            assert!(!one.is_null());
+    /// Size of the pages the arena is committed in, the OS page size
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
 
-            let two = allocator.alloc(layout);
-            assert!(!two.is_null());
+    /// Bytes in use, as counted by `used_bytes`, over the bytes committed so far
+    pub fn used_ratio(&self) -> f32 {
+        let committed = self.page_size * self.pages.load(Ordering::Relaxed) as usize;
+        self.used_bytes() as f32 / committed as f32
+    }
 
-            let three = allocator.alloc(layout);
-            assert!(!three.is_null());
+    /// Makes `alloc` commit another page ahead of demand whenever it leaves `used_ratio` above `ratio`
+    /// Later allocations then find room without growing the arena themselves
+    /// Checking the ratio walks every block, so it's only done while a threshold is set
+    /// Ratios of `1.0` or more, the default, turn it off
+    pub fn set_grow_threshold(&self, ratio: f32) {
+        self.grow_threshold
+            .store(ratio.min(1.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn grow_threshold(&self) -> f32 {
+        f32::from_bits(self.grow_threshold.load(Ordering::Relaxed))
+    }
+
+    /// Allocates a block of `size` bytes from the bins or the list, the body of `alloc` once slabs are ruled out
+    fn alloc_block(&self, size: usize, align: usize) -> *mut u8 {
+        // Keeps the headers of split blocks aligned
+        let size = size.next_multiple_of(align_of::<Header>());
+
+        // Bin blocks' data directly follows the header, so it's only guaranteed header alignment
+        // Binned blocks may sit below an active checkpoint, so they're not reused while there is one
+        if align <= align_of::<Header>()
+            && self.floor.load(Ordering::Relaxed) == 0
+            && let Some(bin) = Self::bin_index(size)
+        {
+            let block = self.pop_bin(bin);
+            if !block.is_null() {
+                return block.get_data();
+            }
+        }
+
+        let mut block = self.find_empty_block(size, align);
+        if block.is_null() {
+            return ptr::null_mut();
+        }
+        let data_ptr = block.get_data();
+
+        let end_of_block = data_ptr.addr() + size;
+        let top_of_buf = self.last_addr();
+        if end_of_block > top_of_buf {
+            return ptr::null_mut();
+        }
+
+        block.mark_used();
+
+        let used_size = block.get_offset() + size;
+        self.split_block(&mut block, used_size);
+        self.grow_ahead();
+        self.defrag_if_slow();
+
+        data_ptr
+    }
+
+    /// Packs allocations of up to `granule` bytes into slabs of 64 slots that share a single header
+    /// Saves the header each tiny allocation would otherwise carry, a slab is freed once all its slots are
+    /// `granule` must be 0, the default, which turns slabs off, or a power of two from 8 to `MAX_ALIGN`
+    /// Only affects new slabs, existing ones keep their slot size
+    pub fn set_slab_granule(&self, granule: usize) {
+        assert!(
+            granule == 0 || (granule.is_power_of_two() && (8..=MAX_ALIGN).contains(&granule)),
+            "Slab granule must be 0 or a power of two from 8 to {MAX_ALIGN}, got {granule}"
+        );
+        self.slab_granule.store(granule, Ordering::Relaxed);
+    }
+
+    pub fn slab_granule(&self) -> usize {
+        self.slab_granule.load(Ordering::Relaxed)
+    }
+
+    /// Distance from a slab's data to its first slot, which keeps the slots aligned to `granule`
+    fn slots_offset(granule: usize) -> usize {
+        size_of::<SlabMeta>().next_multiple_of(granule)
+    }
+
+    fn slab_meta(slab: &HeaderPtr) -> *mut SlabMeta {
+        slab.get_data().cast()
+    }
+
+    fn slot_ptr(slab: &HeaderPtr, slot: usize) -> *mut u8 {
+        let granule = unsafe { (*Self::slab_meta(slab)).granule };
+        unsafe {
+            slab.get_data()
+                .add(Self::slots_offset(granule) + slot * granule)
+        }
+    }
+
+    /// Takes a slot from a slab of `granule` sized slots with room, starting a new slab if none has any
+    /// Slabs below an active checkpoint are left alone, so restoring it frees every slot taken since
+    fn alloc_slot(&self, granule: usize) -> *mut u8 {
+        let floor = self.floor.load(Ordering::Relaxed);
+        let mut slab = self.first_block();
+        while !slab.is_null() {
+            if slab.used() && slab.slab() && slab.addr() >= floor {
+                let meta = Self::slab_meta(&slab);
+                let taken = unsafe { (*meta).taken };
+                if unsafe { (*meta).granule } == granule && taken != u64::MAX {
+                    let slot = taken.trailing_ones() as usize;
+                    unsafe { (*meta).taken |= 1 << slot };
+                    return Self::slot_ptr(&slab, slot);
+                }
+            }
+            slab.set(&self.next_header(&slab));
+        }
+
+        let data = self.alloc_block(Self::slots_offset(granule) + SLAB_SLOTS * granule, granule);
+        if data.is_null() {
+            return ptr::null_mut();
+        }
+        let mut slab = self.find_ptr_block(data);
+        slab.set_slab(true);
+        unsafe { Self::slab_meta(&slab).write(SlabMeta { taken: 1, granule }) };
+
+        Self::slot_ptr(&slab, 0)
+    }
+
+    /// The slab holding the taken slot at `ptr` and the slot's index, None if `ptr` isn't one
+    fn find_slot(&self, ptr: *mut u8) -> Option<(HeaderPtr, usize)> {
+        let mut slab = self.first_block();
+        while !slab.is_null() {
+            if slab.used() && slab.slab() {
+                let SlabMeta { taken, granule } = unsafe { Self::slab_meta(&slab).read() };
+                let offset = ptr.addr().checked_sub(Self::slot_ptr(&slab, 0).addr());
+                if let Some(offset) = offset
+                    && offset < SLAB_SLOTS * granule
+                {
+                    let slot = offset / granule;
+                    let valid = offset.is_multiple_of(granule) && taken & (1 << slot) != 0;
+                    return valid.then_some((slab, slot));
+                }
+            }
+            slab.set(&self.next_header(&slab));
+        }
+
+        None
+    }
+
+    /// Keeps a slot in place while the new size still fits it, otherwise moves it out
+    fn realloc_slot(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let Some((slab, slot)) = self.find_slot(ptr) else {
+            return ptr::null_mut();
+        };
+        if new_size <= unsafe { (*Self::slab_meta(&slab)).granule } {
+            return ptr;
+        }
+
+        let Some(new_layout) = crate::realloc_layout(layout, new_size) else {
+            return ptr::null_mut();
+        };
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe { ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size)) };
+            self.free_slot(slab, slot);
+        }
+
+        new_ptr
+    }
+
+    /// Marks a block of the arena free, parking it in a bin if one serves its size
+    fn free_block(&self, mut block: HeaderPtr) {
+        // The offset in front of the data is zeroed too, only the header is kept
+        unsafe { crate::zeroize(block.add(1).cast(), block.size()) };
+        block.free();
+        block.set_offset(0);
+        Self::fill_free_pattern(&mut block);
+
+        // Blocks freed during a checkpoint stay visible to restore
+        if self.floor.load(Ordering::Relaxed) == 0
+            && let Some(bin) = Self::bin_index(block.size())
+        {
+            self.push_bin(&mut block, bin);
+        }
+    }
+
+    /// Frees a slot, and its slab along with it if that was the last one taken
+    fn free_slot(&self, slab: HeaderPtr, slot: usize) {
+        let meta = Self::slab_meta(&slab);
+        unsafe {
+            crate::zeroize(Self::slot_ptr(&slab, slot), (*meta).granule);
+            (*meta).taken &= !(1 << slot);
+            if (*meta).taken == 0 {
+                self.free_block(slab);
+            }
+        }
+    }
+
+    /// Gives allocations larger than `size` bytes a mapping of their own, outside the arena
+    /// Keeps large objects from fragmenting the arena, which is left to the small ones
+    /// Large objects aren't counted by `used_bytes`, and while a checkpoint is active every allocation stays in the arena
+    /// `usize::MAX`, the default, keeps everything in the arena, half a page is a reasonable threshold otherwise
+    pub fn set_large_threshold(&self, size: usize) {
+        self.large_threshold.store(size, Ordering::Relaxed);
+    }
+
+    pub fn large_threshold(&self) -> usize {
+        self.large_threshold.load(Ordering::Relaxed)
+    }
+
+    /// Distance from a large object's header to its data, which keeps the data aligned to `align`
+    fn large_offset(align: usize) -> usize {
+        size_of::<LargeHeader>().next_multiple_of(align)
+    }
+
+    /// Layout of the mapping holding a large object and its header
+    fn large_mapping(layout: Layout) -> Option<Layout> {
+        let size = Self::large_offset(layout.align()).checked_add(layout.size())?;
+        Layout::from_size_align(size, layout.align()).ok()
+    }
+
+    fn large_data(large: *mut LargeHeader) -> *mut u8 {
+        unsafe {
+            large
+                .cast::<u8>()
+                .add(Self::large_offset((*large).layout.align()))
+        }
+    }
+
+    /// Maps a large object and pushes it onto the list
+    fn alloc_large(&self, layout: Layout) -> *mut u8 {
+        let Some(mapping) = Self::large_mapping(layout) else {
+            return ptr::null_mut();
+        };
+        let large = unsafe { self.large.alloc(mapping) }.cast::<LargeHeader>();
+        if large.is_null() {
+            return ptr::null_mut();
+        }
+
+        let next = self.large_objects.load(Ordering::Relaxed);
+        unsafe {
+            large.write(LargeHeader {
+                next,
+                prev: ptr::null_mut(),
+                layout,
+            })
+        };
+        self.relink(large);
+
+        Self::large_data(large)
+    }
+
+    /// Points the neighbours of `large`, or the head of the list, back at it after it was written or moved
+    fn relink(&self, large: *mut LargeHeader) {
+        unsafe {
+            let prev = (*large).prev;
+            if prev.is_null() {
+                self.large_objects.store(large, Ordering::Relaxed);
+            } else {
+                (*prev).next = large;
+            }
+            if !(*large).next.is_null() {
+                (*(*large).next).prev = large;
+            }
+        }
+    }
+
+    /// The header of the large object at `ptr`, null if `ptr` isn't one
+    fn find_large(&self, ptr: *mut u8) -> *mut LargeHeader {
+        let mut large = self.large_objects.load(Ordering::Relaxed);
+        while !large.is_null() && Self::large_data(large) != ptr {
+            large = unsafe { (*large).next };
+        }
+
+        large
+    }
+
+    /// Unlinks a large object and unmaps it
+    fn free_large(&self, large: *mut LargeHeader) {
+        unsafe {
+            let LargeHeader { next, prev, layout } = large.read();
+            if prev.is_null() {
+                self.large_objects.store(next, Ordering::Relaxed);
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+
+            crate::zeroize(Self::large_data(large), layout.size());
+            if let Some(mapping) = Self::large_mapping(layout) {
+                self.large.dealloc(large.cast(), mapping);
+            }
+        }
+    }
+
+    pub(crate) fn free_large_objects(&self) {
+        loop {
+            let large = self.large_objects.load(Ordering::Relaxed);
+            if large.is_null() {
+                break;
+            }
+            self.free_large(large);
+        }
+    }
+
+    /// Remaps a large object that stays above the threshold, otherwise moves it into the arena
+    fn realloc_large(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let large = self.find_large(ptr);
+        if large.is_null() {
+            return ptr::null_mut();
+        }
+        let Some(new_layout) = crate::realloc_layout(layout, new_size) else {
+            return ptr::null_mut();
+        };
+
+        if new_size > self.large_threshold() {
+            let old_layout = unsafe { (*large).layout };
+            let (Some(mapping), Some(new_mapping)) = (
+                Self::large_mapping(old_layout),
+                Self::large_mapping(new_layout),
+            ) else {
+                return ptr::null_mut();
+            };
+            let moved = unsafe {
+                self.large
+                    .realloc(large.cast(), mapping, new_mapping.size())
+            }
+            .cast::<LargeHeader>();
+            if moved.is_null() {
+                return ptr::null_mut();
+            }
+            unsafe { (*moved).layout = new_layout };
+            self.relink(moved);
+
+            return Self::large_data(moved);
+        }
+
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe { ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size)) };
+            self.free_large(large);
+        }
+
+        new_ptr
+    }
+
+    pub fn search_stats(&self) -> SearchStats {
+        SearchStats {
+            fast: self.fast_searches.load(Ordering::Relaxed),
+            slow: self.slow_searches.load(Ordering::Relaxed),
+            defrags: self.defrags.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Makes `alloc` coalesce every run of free blocks once more than `ratio` of its recent searches were slow
+    /// The share is checked every 64 searches, so a few slow ones don't set it off
+    /// Ratios of `1.0` or more, the default, turn it off
+    pub fn set_defrag_threshold(&self, ratio: f32) {
+        self.defrag_threshold
+            .store(ratio.min(1.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn defrag_threshold(&self) -> f32 {
+        f32::from_bits(self.defrag_threshold.load(Ordering::Relaxed))
+    }
+
+    /// Coalesces the arena if the last window of searches was slow too often
+    fn defrag_if_slow(&self) {
+        if self.window_searches.load(Ordering::Relaxed) < DEFRAG_WINDOW {
+            return;
+        }
+        let searches = self.window_searches.swap(0, Ordering::Relaxed);
+        let slow = self.window_slow.swap(0, Ordering::Relaxed);
+
+        let threshold = self.defrag_threshold();
+        if threshold < 1.0 && slow as f32 / searches as f32 > threshold {
+            self.coalesce();
+            self.defrags.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Commits the next page if usage is past the threshold, handing it to the block at the top of the arena
+    /// or to a new free block if that one isn't available
+    fn grow_ahead(&self) {
+        let threshold = self.grow_threshold();
+        if threshold >= 1.0 || self.used_ratio() <= threshold {
+            return;
+        }
+
+        let mut top = self.first_block();
+        loop {
+            let next = self.next_header(&top);
+            if next.is_null() {
+                break;
+            }
+            top.set(&next);
+        }
+
+        let old_last = self.last_addr();
+        if self.request_new_page().is_err() {
+            return;
+        }
+        if !self.unavailable(&top) && top.last_addr() == Some(old_last) {
+            top.set_filled(false);
+            top.add_size(self.page_size);
+        } else {
+            let mut new_block = HeaderPtr::new(
+                self.buf_ptr()
+                    .wrapping_add(old_last - self.buf_ptr().addr()),
+            );
+            unsafe { new_block.write(Header::new(self.page_size - size_of::<Header>(), 0)) };
+            new_block.set_sentinel(true);
+            top.set_sentinel(false);
+            self.blocks.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Length of the whole reservation
+    fn arena_len(&self) -> usize {
+        self.page_size * MAX_PAGES
+    }
+
+    fn first_block(&self) -> HeaderPtr {
+        HeaderPtr(self.buf_ptr() as *mut Header)
+    }
+
+    fn last_addr(&self) -> usize {
+        let pages = self.pages.load(Ordering::Relaxed) as usize;
+        unsafe { self.buf_ptr().add(self.page_size * pages).addr() }
+    }
+
+    fn buf_ptr(&self) -> *mut u8 {
+        unsafe { (*self.buf).get().cast() }
+    }
+
+    /// Finds the block representing the given data pointer
+    fn find_ptr_block(&self, ptr: *mut u8) -> HeaderPtr {
+        let mut block = self.first_block();
+        while !block.is_null() && block.get_data() != ptr {
+            block.set(&self.next_header(&block));
+        }
+
+        block
+    }
+
+    /// Number of blocks in the list, used or free, without walking it
+    pub fn number_of_blocks(&self) -> usize {
+        self.blocks.load(Ordering::Relaxed)
+    }
+
+    /// Returns the index of the bin serving allocations of exactly `size` bytes, if any
+    fn bin_index(size: usize) -> Option<usize> {
+        BIN_SIZES.iter().position(|&bin_size| bin_size == size)
+    }
+
+    /// Parks a freed block in its size class bin
+    /// The link to the next binned block is stored in the block's data
+    fn push_bin(&self, block: &mut HeaderPtr, bin: usize) {
+        block.set_binned(true);
+        let next = self.bins[bin].load(Ordering::Relaxed);
+        unsafe { block.add(1).cast::<*mut Header>().write(next) };
+        self.bins[bin].store(block.0, Ordering::Relaxed);
+    }
+
+    /// Pops a block from the given bin and marks it used
+    fn pop_bin(&self, bin: usize) -> HeaderPtr {
+        let head = self.bins[bin].load(Ordering::Relaxed);
+        if head.is_null() {
+            return HeaderPtr::null();
+        }
+
+        let mut block = HeaderPtr::new(head);
+        Self::check_free_pattern(&block);
+        let next = unsafe { block.add(1).cast::<*mut Header>().read() };
+        self.bins[bin].store(next, Ordering::Relaxed);
+        unsafe { crate::zeroize(block.add(1).cast(), size_of::<*mut Header>()) };
+
+        block.set_binned(false);
+        block.set_offset(0);
+        block.mark_used();
+        block
+    }
+
+    /// Grows `block` to hold `size` bytes by merging it with the free space around it, previous block, itself and following blocks
+    /// The free blocks that follow are used alone when they're enough, so the data stays put and `block` is unchanged
+    /// Otherwise, if `copy` is Some, the free block in front is merged too, `block` is moved back to it
+    /// and the first `copy` bytes slide back with an overlapping copy, realigned to `align`
+    /// Pages are committed when the span reaches the top of the arena, absorbed headers are cleared
+    /// and leftover space is split off into a new free block
+    /// Nothing is touched if the whole span is too small or the arena can't grow far enough
+    fn grow_in_place(
+        &self,
+        block: &mut HeaderPtr,
+        size: usize,
+        align: usize,
+        copy: Option<usize>,
+    ) -> bool {
+        let Some(size) = size.checked_next_multiple_of(align_of::<Header>()) else {
+            return false;
+        };
+
+        // The free blocks following `block`, up to the next one in use or the top of the arena
+        let mut frontier = self.next_header(block);
+        while !frontier.is_null() && !self.unavailable(&frontier) {
+            Self::check_free_pattern(&frontier);
+            frontier.set(&self.next_header(&frontier));
+        }
+        // Reaching the top, the span can grow into pages that aren't committed yet
+        let limit = if frontier.is_null() {
+            self.buf_ptr().addr() + self.arena_len()
+        } else {
+            frontier.addr()
+        };
+        // The reservation never wraps, so an end that would can't fit either
+        let end_for = |start: &HeaderPtr, offset: usize| {
+            let end = start.addr().checked_add(size_of::<Header>() + offset)?;
+            end.checked_add(size).filter(|&end| end <= limit)
+        };
+
+        let mut start = HeaderPtr::new(block.0);
+        let mut offset = block.get_offset();
+        let mut end = end_for(&start, offset);
+        if end.is_none() && copy.is_some() && align <= MAX_ALIGN {
+            let previous = self.previous_block(block);
+            if !previous.is_null() && !self.unavailable(&previous) {
+                offset = crate::padding_for(unsafe { previous.add(1).cast::<u8>() }, align);
+                end = end_for(&previous, offset);
+                start = previous;
+            }
+        }
+        let Some(end) = end else {
+            return false;
+        };
+
+        let span_end = if frontier.is_null() {
+            while self.last_addr() < end {
+                if self.request_new_page().is_err() {
+                    return false;
+                }
+            }
+            self.last_addr()
+        } else {
+            frontier.addr()
+        };
+
+        let mut absorbed = self.next_header(block);
+        while !absorbed.is_null() && absorbed.0 != frontier.0 {
+            let next = self.next_header(&absorbed);
+            unsafe { absorbed.write_bytes(0, 1) };
+            self.blocks.fetch_sub(1, Ordering::Relaxed);
+            absorbed.set(&next);
+        }
+        if start.0 != block.0 {
+            // The block's own header is absorbed into the one in front
+            self.blocks.fetch_sub(1, Ordering::Relaxed);
+            Self::check_free_pattern(&start);
+            // The data overlaps its new place whenever the block in front is smaller than it, so it's a memmove
+            let data = unsafe { start.add(1).cast::<u8>().add(offset) };
+            unsafe { ptr::copy(block.get_data(), data, copy.unwrap_or(0)) };
+            start.set_offset(offset);
+        }
+
+        start.set_size(span_end - start.addr() - size_of::<Header>());
+        // A span reaching the top of the arena ends the list, until its leftover is split off
+        start.set_sentinel(frontier.is_null());
+        self.split_block(&mut start, offset + size);
+        start.mark_used();
+        block.set(&start);
+
+        true
+    }
+
+    /// Finds the block right before `block`, null if it's the first one
+    fn previous_block(&self, block: &HeaderPtr) -> HeaderPtr {
+        let mut previous = HeaderPtr::null();
+        let mut current = self.first_block();
+        while !current.is_null() && current.0 != block.0 {
+            previous.set(&current);
+            current.set(&self.next_header(&current));
+        }
+        if current.is_null() {
+            return HeaderPtr::null();
+        }
+
+        previous
+    }
+
+    /// Shrinks `block` to `used_size` bytes and turns the rest into a new free block
+    /// Leftovers that can't hold a header and `MIN_BLOCK_SIZE` bytes stay part of the block
+    fn split_block(&self, block: &mut HeaderPtr, used_size: usize) {
+        let Some(leftover) = block
+            .size()
+            .checked_sub(size_of::<Header>() + used_size)
+            .filter(|&leftover| leftover >= MIN_BLOCK_SIZE)
+        else {
+            return;
+        };
+
+        let sentinel = block.sentinel();
+        block.set_sentinel(false);
+        block.set_size(used_size);
+        let mut tail = self.next_header_unchecked(block);
+        unsafe { tail.write(Header::new(leftover, 0)) };
+        tail.set_sentinel(sentinel);
+        self.blocks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Shrinks a used block to hold `new_size` bytes and frees the tail
+    /// `split_block` keeps tails too small for a header and `MIN_BLOCK_SIZE` bytes in the block
+    /// A tail of a bin's size goes straight into it, so the next small allocation reuses it
+    fn shrink_block(&self, block: &mut HeaderPtr, new_size: usize) {
+        let used_size = block.get_offset() + new_size.next_multiple_of(align_of::<Header>());
+        let old_size = block.size();
+        self.split_block(block, used_size);
+        if block.size() == old_size {
+            return;
+        }
+
+        let mut tail = self.next_header_unchecked(block);
+        unsafe { crate::zeroize(tail.add(1).cast(), tail.size()) };
+        Self::fill_free_pattern(&mut tail);
+        if self.floor.load(Ordering::Relaxed) == 0
+            && let Some(bin) = Self::bin_index(tail.size())
+        {
+            self.push_bin(&mut tail, bin);
+        }
+    }
+
+    /// Fills the data of a freed block with `FREE_PATTERN`, so later writes through stale pointers can be detected
+    fn fill_free_pattern(block: &mut HeaderPtr) {
+        if !cfg!(feature = "heap-check") {
+            return;
+        }
+
+        unsafe {
+            block
+                .add(1)
+                .cast::<u8>()
+                .write_bytes(FREE_PATTERN, block.size())
+        };
+        block.set_filled(true);
+    }
+
+    /// Panics if a filled free block's data was written to since it was freed
+    #[track_caller]
+    fn check_free_pattern(block: &HeaderPtr) {
+        if !cfg!(feature = "heap-check") || !block.filled() {
+            return;
+        }
+
+        // Binned blocks keep the link to the next block at the start of their data
+        let skip = if block.binned() {
+            size_of::<*mut Header>()
+        } else {
+            0
+        };
+        let data = unsafe { block.add(1).cast::<u8>() };
+        let intact = (skip..block.size()).all(|i| unsafe { data.add(i).read() } == FREE_PATTERN);
+        assert!(
+            intact,
+            "free block at {:p} was written to after it was freed",
+            block.0
+        );
+    }
+
+    // Commits a new page at the top of the reserved arena
+    fn request_new_page(&self) -> Result<(), AllocFailure> {
+        let pages = self.pages.load(Ordering::Relaxed) as usize;
+        if pages == MAX_PAGES {
+            return Err(AllocFailure::ArenaFull);
+        }
+
+        let page = unsafe { self.buf_ptr().add(self.page_size * pages) };
+        if unsafe { self.source.map(page, self.page_size) }.is_null() {
+            return Err(AllocFailure::CommitFailed);
+        }
+
+        let _ = self.pages.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn free_allocator(self) {
+        self.free_large_objects();
+        let pages = self.pages.load(Ordering::Relaxed) as usize;
+        unsafe {
+            self.buf.cast::<u8>().write_bytes(0, self.page_size * pages);
+            self.source.release(self.buf_ptr(), self.arena_len());
+        };
+    }
+}
+
+impl Default for LinkedListAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: PageSource> fmt::Debug for LinkedListAllocator<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let committed = self.page_size * self.pages.load(Ordering::Relaxed) as usize;
+        f.debug_struct("LinkedListAllocator")
+            .field("capacity", &self.arena_len())
+            .field("committed", &committed)
+            .field("used", &self.used_bytes())
+            .field("blocks", &self.number_of_blocks())
+            .finish()
+    }
+}
+
+unsafe impl<S: PageSource> GlobalAlloc for LinkedListAllocator<S> {
+    #[track_caller]
+    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
+        let align = crate::checked_align(layout.align());
+        if align > MAX_ALIGN {
+            return ptr::null_mut();
+        }
+
+        let granule = self.slab_granule();
+        if layout.size() <= granule && align <= granule {
+            return self.alloc_slot(granule);
+        }
+        // Checkpoints only cover the arena, so while one is active everything stays in it
+        if layout.size() > self.large_threshold() && self.floor.load(Ordering::Relaxed) == 0 {
+            return self.alloc_large(layout);
+        }
+
+        self.alloc_block(layout.size(), align)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: core::alloc::Layout) {
+        if !self.owns(ptr) {
+            let large = self.find_large(ptr);
+            if !large.is_null() {
+                self.free_large(large);
+            }
+            return;
+        }
+        let block = self.find_ptr_block(ptr);
+        if block.is_null() {
+            if let Some((slab, slot)) = self.find_slot(ptr) {
+                self.free_slot(slab, slot);
+            }
+            return;
+        }
+
+        self.free_block(block);
+    }
+
+    #[track_caller]
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: core::alloc::Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        // Same as `alloc`, a block can't be placed for a larger alignment, even if it wouldn't move
+        if crate::checked_align(layout.align()) > MAX_ALIGN {
+            return ptr::null_mut();
+        }
+        if !self.owns(ptr) {
+            return self.realloc_large(ptr, layout, new_size);
+        }
+
+        // Nothing is changed until the new size is known to fit, so a failed realloc leaves the block intact
+        let mut header_ptr = self.find_ptr_block(ptr);
+        if header_ptr.is_null() {
+            return self.realloc_slot(ptr, layout, new_size);
+        }
+        // A block that was already freed, or parked in a bin, belongs to the allocator again
+        if !header_ptr.used() {
+            return ptr::null_mut();
+        }
+        if new_size == layout.size() {
+            return ptr;
+        }
+        // Shrinking stays in place and frees the tail
+        if new_size < layout.size() {
+            self.shrink_block(&mut header_ptr, new_size);
+            return ptr;
+        }
+        // Growth into the block's slack, up to its `allocated_size`, needs no neighbours and no split
+        if new_size <= header_ptr.usable() {
+            return ptr;
+        }
+        // Then try merging with the free blocks on either side, only sliding back if the ones after it aren't enough
+        let copy = layout.size().min(new_size);
+        if self.grow_in_place(&mut header_ptr, new_size, layout.align(), Some(copy)) {
+            return header_ptr.get_data();
+        }
+        // Otherwise move it to a block found by `alloc`, with the original alignment so the destination satisfies it too
+        let Some(new_layout) = crate::realloc_layout(layout, new_size) else {
+            return ptr::null_mut();
+        };
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe {
+                ptr::copy_nonoverlapping(ptr, new_ptr, copy);
+                self.dealloc(ptr, layout);
+            }
+        }
+
+        new_ptr
+    }
+
+    #[track_caller]
+    unsafe fn alloc_zeroed(&self, layout: core::alloc::Layout) -> *mut u8 {
+        let size = layout.size();
+        unsafe {
+            let ptr = self.alloc(layout);
+            if ptr.is_null() {
+                return ptr::null_mut();
+            }
+
+            ptr.write_bytes(0, size);
+            ptr
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::{
+        alloc::Layout,
+        cell::{Cell, RefCell},
+        ffi::c_void,
+    };
+
+    use super::*;
+    use crate::page_source::FixedAddress;
+
+    // The arena sits behind a raw pointer and nothing synchronises the block list,
+    // `ShardedAllocator` puts it behind locks to share it
+    crate::assert_not_impl!(LinkedListAllocator, Send);
+    crate::assert_not_impl!(LinkedListAllocator, Sync);
+
+    fn page_size() -> usize {
+        *crate::page_allocator::PAGE_SIZE
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Call {
+        Reserve(usize),
+        /// Offsets into the reservation and lengths
+        Map(usize, usize),
+        Unmap(usize, usize),
+        Release(usize, usize),
+    }
+
+    /// Backs the arena with heap memory and records every call made to it
+    #[derive(Default)]
+    struct MockSource {
+        base: Cell<usize>,
+        calls: RefCell<Vec<Call>>,
+        /// Number of further `map` calls that succeed, unlimited if None
+        map_budget: Cell<Option<usize>>,
+    }
+
+    impl MockSource {
+        fn layout(len: usize) -> Layout {
+            Layout::from_size_align(len, page_size()).unwrap()
+        }
+    }
+
+    impl PageSource for MockSource {
+        unsafe fn reserve(&self, len: usize) -> *mut u8 {
+            let base = unsafe { std::alloc::alloc(Self::layout(len)) };
+            self.base.set(base.addr());
+            self.calls.borrow_mut().push(Call::Reserve(len));
+            base
+        }
+
+        unsafe fn map(&self, hint: *mut u8, len: usize) -> *mut u8 {
+            if let Some(budget) = self.map_budget.get() {
+                if budget == 0 {
+                    return ptr::null_mut();
+                }
+                self.map_budget.set(Some(budget - 1));
+            }
+
+            let offset = hint.addr() - self.base.get();
+            self.calls.borrow_mut().push(Call::Map(offset, len));
+            hint
+        }
+
+        unsafe fn unmap(&self, ptr: *mut u8, len: usize) {
+            let offset = ptr.addr() - self.base.get();
+            self.calls.borrow_mut().push(Call::Unmap(offset, len));
+        }
+
+        unsafe fn release(&self, ptr: *mut u8, len: usize) {
+            let offset = ptr.addr() - self.base.get();
+            self.calls.borrow_mut().push(Call::Release(offset, len));
+            unsafe { std::alloc::dealloc(ptr, Self::layout(len)) };
+        }
+    }
+
+    #[test]
+    fn alloc_chunks() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 16]>();
+
+        unsafe {
+            let chunk = allocator.alloc(layout);
+            assert!(!chunk.is_null());
+            allocator.dealloc(chunk, layout);
+
+            let one = allocator.alloc(layout);
+            assert!(!one.is_null());
+
+            let two = allocator.alloc(layout);
+            assert!(!two.is_null());
+
+            let three = allocator.alloc(layout);
+            assert!(!three.is_null());
 
             allocator.dealloc(three, layout);
             allocator.dealloc(one, layout);
@@ -576,40 +1864,1126 @@ mod test {
     }
 
     #[test]
-    fn overflow() {
+    fn overflow() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 5000]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            assert!(!one.is_null());
+            allocator.dealloc(one, layout);
+
+            let two = allocator.alloc(layout);
+            assert!(!two.is_null());
+            allocator.dealloc(two, layout);
+        }
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn zeroed() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 16]>();
+
+        unsafe {
+            let one = allocator.alloc_zeroed(layout);
+            assert!(!one.is_null());
+
+            let two = allocator.alloc_zeroed(layout);
+            assert!(!two.is_null());
+
+            let two_sum: u8 = (0..16).into_iter().map(|i| *(two.wrapping_add(i))).sum();
+            let one_sum: u8 = (0..16).into_iter().map(|i| *(one.wrapping_add(i))).sum();
+            assert_eq!(two_sum, 0);
+            assert_eq!(one_sum, 0);
+
+            allocator.dealloc(two, layout);
+            allocator.dealloc(one, layout);
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn realloc() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 16]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            assert!(!one.is_null());
+
+            let two = allocator.alloc(layout);
+            assert!(!two.is_null());
+
+            allocator.realloc(two, layout, 32);
+            allocator.dealloc(one, layout);
+            allocator.dealloc(two, Layout::new::<[u8; 32]>());
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn realloc_relocates() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 16]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            assert!(!one.is_null());
+
+            (0..16).for_each(|i| one.add(i).write(i as u8));
+            // Keeps `one` from growing in place, so it has to move
+            let two = allocator.alloc(layout);
+            assert!(!two.is_null());
+
+            let moved = allocator.realloc(one, layout, 64);
+            assert!(!moved.is_null());
+            assert!(moved > two);
+            (0..16).for_each(|i| assert_eq!(moved.add(i).read(), i as u8));
+
+            // The old block is freed and handed back out
+            assert!(!allocator.first_block().used());
+            assert_eq!(allocator.alloc(layout), one);
+            allocator.dealloc(one, layout);
+            allocator.dealloc(two, layout);
+            allocator.dealloc(moved, Layout::new::<[u8; 64]>());
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn merge() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 2000]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            assert!(!one.is_null());
+            allocator.dealloc(one, layout);
+
+            let layout = Layout::new::<[u8; 3080]>();
+            let two = allocator.alloc(layout);
+            assert!(!two.is_null());
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn small_bin_reuse() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 16]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            assert!(!one.is_null());
+
+            let two = allocator.alloc(layout);
+            assert!(!two.is_null());
+
+            allocator.dealloc(one, layout);
+            assert!(allocator.find_ptr_block(one).binned());
+
+            let three = allocator.alloc(layout);
+            assert_eq!(three, one);
+            assert!(!allocator.find_ptr_block(three).binned());
+
+            allocator.dealloc(three, layout);
+            allocator.dealloc(two, layout);
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn realloc_shrink_tail() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 256]>();
+
+        unsafe {
+            // Leaves a 32 byte tail, the size of the largest bin
+            let one = allocator.alloc(layout);
+            assert_eq!(allocator.realloc(one, layout, 208), one);
+            let tail = one.add(208 + size_of::<Header>());
+            assert!(allocator.find_ptr_block(tail).binned());
+            assert_eq!(allocator.alloc(Layout::new::<[u8; 32]>()), tail);
+
+            // A larger tail becomes a free block
+            let two = allocator.alloc(layout);
+            assert_eq!(allocator.realloc(two, layout, 64), two);
+            let tail = two.add(64 + size_of::<Header>());
+            assert_eq!(allocator.alloc(Layout::new::<[u8; 128]>()), tail);
+
+            // Too small to hold a header, so it stays part of the block
+            let blocks = allocator.number_of_blocks();
+            assert_eq!(
+                allocator.realloc(tail, Layout::new::<[u8; 128]>(), 120),
+                tail
+            );
+            assert_eq!(allocator.number_of_blocks(), blocks);
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn offsets() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 64]>();
+
+        unsafe {
+            let _ = allocator.alloc(layout);
+            let two = allocator.alloc(layout);
+            let offset = allocator.offset_of(two).unwrap();
+            assert_eq!(offset, 64 + size_of::<Header>() * 2);
+            assert_eq!(allocator.ptr_of(offset), two);
+        }
+        let committed = allocator.as_ptr_range();
+        assert_eq!(allocator.offset_of(committed.end), None);
+        assert!(allocator.ptr_of(allocator.page_size()).is_null());
+
+        allocator.free_allocator();
+    }
+
+    // Touches no mmap'd memory, so it also runs under Miri
+    #[test]
+    fn header_accessors() {
+        let mut header = Header::new(64, 0);
+        let mut block = HeaderPtr::new(&raw mut header);
+
+        block.mark_used();
+        block.add_size(32);
+        block.set_offset(8);
+        block.set_size(block.size() - 16);
+        block.set_binned(true);
+        block.set_offset(24);
+        block.set_binned(false);
+        assert_eq!((block.size(), block.get_offset()), (80, 24));
+        assert!(block.used() && !block.binned());
+        assert_eq!(
+            block.get_data().addr(),
+            block.addr() + size_of::<Header>() + 24
+        );
+
+        block.free();
+        assert!(!block.used());
+        assert_eq!(header.size, 80);
+        assert_eq!(header.offset & !FLAG_BITS, 24);
+    }
+
+    #[test]
+    fn fixed_address() {
+        let base = 0x2100_0000_0000;
+        let allocator = LinkedListAllocator::with_source(FixedAddress::new(base));
+        assert_eq!(allocator.as_ptr_range().start.addr(), base);
+        let ptr = unsafe { allocator.alloc(Layout::new::<u64>()) };
+        assert_eq!(ptr.addr(), base + size_of::<Header>());
+
+        // The address is taken while the first arena holds it
+        assert_eq!(
+            LinkedListAllocator::try_with_source(FixedAddress::new(base)).err(),
+            Some(AllocFailure::ReserveFailed)
+        );
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn large_objects() {
+        let allocator = LinkedListAllocator::new();
+        allocator.set_large_threshold(page_size() / 2);
+        let large = Layout::from_size_align(10 * 1024, 16).unwrap();
+        let small = Layout::new::<[u8; 32]>();
+
+        unsafe {
+            let one = allocator.alloc(large);
+            let two = allocator.alloc(small);
+            let three = allocator.alloc(large);
+            assert!(!allocator.owns(one) && !allocator.owns(three));
+            assert!(one.addr().is_multiple_of(16));
+            assert!(allocator.owns(two));
+            assert_eq!(allocator.used_bytes(), 32);
+            assert_eq!(allocator.allocated_size(one), Some(10 * 1024));
+            (0..large.size()).for_each(|i| one.add(i).write(i as u8));
+
+            // Stays mapped on its own while it's above the threshold, the list follows it if it moves
+            let grown = allocator.realloc(one, large, 64 * 1024);
+            assert!(!allocator.owns(grown));
+            assert!((0..large.size()).all(|i| grown.add(i).read() == i as u8));
+            assert!(!allocator.find_large(three).is_null());
+
+            allocator.dealloc(grown, Layout::from_size_align(64 * 1024, 16).unwrap());
+            allocator.dealloc(two, small);
+            allocator.dealloc(three, large);
+            assert!(allocator.large_objects.load(Ordering::Relaxed).is_null());
+            assert_eq!(allocator.large.stats().maps, allocator.large.stats().unmaps);
+            assert!(!allocator.first_block().used());
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn slabs() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<u64>();
+        allocator.set_slab_granule(8);
+
+        unsafe {
+            let ptrs = (0..1000u64)
+                .map(|i| {
+                    let ptr = allocator.alloc(layout).cast::<u64>();
+                    ptr.write(i);
+                    ptr
+                })
+                .collect::<Vec<_>>();
+            assert!(
+                ptrs.iter()
+                    .enumerate()
+                    .all(|(i, ptr)| ptr.read() == i as u64)
+            );
+            assert_eq!(allocator.allocated_size(ptrs[3].cast()), Some(8));
+
+            // 16 slabs and the rest of the arena, rather than a header for every object
+            let headers = allocator.number_of_blocks() * size_of::<Header>();
+            assert!(headers < 1000 * size_of::<Header>() / 10);
+            assert!(allocator.used_bytes() < 1000 * (8 + size_of::<Header>() / 2));
+            assert_eq!(allocator.validate(), Ok(()));
+
+            // Fits its slot, then outgrows it
+            assert_eq!(allocator.realloc(ptrs[0].cast(), layout, 4), ptrs[0].cast());
+            let moved = allocator.realloc(ptrs[0].cast(), layout, 64).cast::<u64>();
+            assert_eq!(moved.read(), 0);
+            assert_eq!(allocator.allocated_size(ptrs[0].cast()), None);
+
+            // Emptied slabs are freed along with their last slot
+            ptrs[1..]
+                .iter()
+                .for_each(|&ptr| allocator.dealloc(ptr.cast(), layout));
+            assert_eq!(allocator.used_bytes(), 64);
+            assert_eq!(allocator.drain().collect::<Vec<_>>(), [moved.cast()]);
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn adaptive_defrag() {
+        let allocator = LinkedListAllocator::new();
+        let small = Layout::new::<[u8; 48]>();
+        let large = Layout::new::<[u8; 112]>();
+        allocator.set_defrag_threshold(0.5);
+
+        unsafe {
+            let blocks = (0..DEFRAG_WINDOW * 2)
+                .map(|_| allocator.alloc(small))
+                .collect::<Vec<_>>();
+            blocks.iter().for_each(|&ptr| allocator.dealloc(ptr, small));
+            let stats = allocator.search_stats();
+            assert_eq!(stats.defrags, 0);
+
+            // Every larger block has to be stitched together from the small ones
+            let _large = (0..DEFRAG_WINDOW)
+                .map(|_| allocator.alloc(large))
+                .collect::<Vec<_>>();
+            let stats = allocator.search_stats();
+            assert!(stats.slow >= DEFRAG_WINDOW);
+            assert_eq!(stats.defrags, 1);
+            assert_eq!(allocator.validate(), Ok(()));
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn cached_block_count() {
+        let allocator = LinkedListAllocator::new();
+        let page = allocator.page_size();
+        let check = |step: &str| {
+            let mut walked = 0;
+            let mut block = allocator.first_block();
+            while !block.is_null() {
+                walked += 1;
+                block.set(&allocator.next_header(&block));
+            }
+            assert_eq!(allocator.number_of_blocks(), walked, "after {step}");
+        };
+        let layout = |size| Layout::from_size_align(size, 8).unwrap();
+
+        unsafe {
+            let ptrs = [24, 100, 64, 300].map(|size| allocator.alloc(layout(size)));
+            check("splitting allocations");
+            allocator.dealloc(ptrs[1], layout(100));
+            allocator.dealloc(ptrs[2], layout(64));
+            let merged = allocator.alloc(layout(150));
+            check("merging freed blocks");
+
+            let grown = allocator.realloc(ptrs[0], layout(24), 64);
+            check("growing into free blocks");
+            let _ = allocator.realloc(ptrs[3], layout(300), 40);
+            check("shrinking");
+            let spanning = allocator.alloc(layout(page * 2));
+            check("committing pages");
+            let placed = allocator.alloc_at(page * 4, layout(32));
+            check("placing a block");
+
+            allocator.dealloc(spanning, layout(page * 2));
+            allocator.dealloc(placed, layout(32));
+            allocator.reclaim();
+            check("reclaiming");
+
+            allocator.set_grow_threshold(0.0);
+            let _ = allocator.alloc(layout(16));
+            check("growing ahead");
+
+            allocator.dealloc(merged, layout(150));
+            allocator.dealloc(grown, layout(64));
+            allocator.drain().for_each(drop);
+            check("draining");
+        }
+        assert_eq!(allocator.validate(), Ok(()));
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn drain() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 64]>();
+
+        unsafe {
+            let mut live = [0; 3].map(|_| allocator.alloc(layout));
+            let freed = allocator.alloc(Layout::new::<[u8; 16]>());
+            // Spans pages, so there's more than one page to free
+            live[1] = allocator.realloc(live[1], layout, allocator.page_size() * 2);
+            allocator.dealloc(freed, Layout::new::<[u8; 16]>());
+
+            let mut drained = allocator.drain().collect::<Vec<_>>();
+            drained.sort();
+            live.sort();
+            assert_eq!(drained, live);
+        }
+        assert_eq!(allocator.used_bytes(), 0);
+        assert_eq!(allocator.number_of_blocks(), 1);
+        assert_eq!(allocator.validate(), Ok(()));
+        assert_eq!(allocator.bins[1].load(Ordering::Relaxed), ptr::null_mut());
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn realloc_over_aligned() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 64]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            one.write_bytes(0xAB, 64);
+
+            let over_aligned = Layout::from_size_align(64, MAX_ALIGN * 2).unwrap();
+            assert!(allocator.realloc(one, over_aligned, 128).is_null());
+            assert!(allocator.realloc(one, over_aligned, 64).is_null());
+            assert_eq!(allocator.allocated_size(one), Some(64));
+            assert!((0..64).all(|i| one.add(i).read() == 0xAB));
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn realloc_tracked() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 64]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            let two = allocator.alloc(layout);
+
+            // Only free space follows `two`
+            assert_eq!(allocator.realloc_tracked(two, layout, 128), (two, false));
+            // `one` is boxed in by `two`
+            let (moved, was_moved) = allocator.realloc_tracked(one, layout, 128);
+            assert!(was_moved);
+            assert_ne!(moved, one);
+
+            let grown = Layout::new::<[u8; 128]>();
+            let (failed, was_moved) = allocator.realloc_tracked(moved, grown, usize::MAX / 2);
+            assert!(failed.is_null());
+            assert!(!was_moved);
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn validate() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 64]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            let _two = allocator.alloc(layout);
+            assert_eq!(allocator.validate(), Ok(()));
+
+            let mut block = allocator.find_ptr_block(one);
+            let header = block.0.read();
+            let corrupt = |write: &dyn Fn(&mut HeaderPtr)| {
+                write(&mut HeaderPtr::new(block.0));
+                let result = allocator.validate();
+                block.0.write(header);
+                result
+            };
+
+            assert_eq!(
+                corrupt(&|block| block.set_sentinel(true)),
+                Err("the sentinel sits before the end of the arena")
+            );
+            assert_eq!(
+                corrupt(&|block| block.set_size(usize::MAX / 2)),
+                Err("a block extends past the end of the committed arena")
+            );
+            assert_eq!(
+                corrupt(&|block| block.set_offset(128)),
+                Err("a block's alignment offset is larger than the block")
+            );
+            assert_eq!(
+                corrupt(&|block| block.set_binned(true)),
+                Err("a block is both used and binned")
+            );
+
+            block.set_binned(false);
+            assert_eq!(allocator.validate(), Ok(()));
+
+            // The walk only stops at the sentinel, so the top block has to carry it
+            let mut top = allocator.first_block();
+            while !allocator.next_header(&top).is_null() {
+                top.set(&allocator.next_header(&top));
+            }
+            top.set_sentinel(false);
+            assert_eq!(
+                allocator.validate(),
+                Err("the block at the top of the arena isn't the sentinel")
+            );
+            top.set_sentinel(true);
+            assert_eq!(allocator.validate(), Ok(()));
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn zero_size_blocks() {
+        let allocator = LinkedListAllocator::new();
+        let empty = Layout::new::<()>();
+        let layout = Layout::new::<[u8; 64]>();
+
+        unsafe {
+            let zero = allocator.alloc(empty);
+            let one = allocator.alloc(layout);
+            let zero_block = allocator.find_ptr_block(zero);
+            assert!(zero_block.used());
+            assert_eq!(zero_block.size(), 0);
+
+            // The walk carries on past the empty block instead of ending the list there
+            assert_eq!(allocator.next_header(&zero_block).get_data(), one);
+            assert_eq!(allocator.number_of_blocks(), 3);
+            assert_eq!(allocator.pages.load(Ordering::Relaxed), 1);
+            assert_eq!(allocator.validate(), Ok(()));
+
+            allocator.dealloc(zero, empty);
+            let two = allocator.alloc(layout);
+            assert!(two > one);
+            assert_eq!(allocator.pages.load(Ordering::Relaxed), 1);
+            assert_eq!(allocator.validate(), Ok(()));
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn limits() {
+        assert!(LinkedListAllocator::MAX_ALIGN.is_power_of_two());
+    }
+
+    #[test]
+    fn realloc_freed() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 16]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            let two = allocator.alloc(Layout::new::<[u8; 48]>());
+            allocator.dealloc(one, layout);
+            let blocks = allocator.number_of_blocks();
+
+            // `one` sits in a bin, none of these may take it back out or resize it
+            for new_size in [8, 16, 200] {
+                assert!(allocator.realloc(one, layout, new_size).is_null());
+            }
+            assert_eq!(allocator.number_of_blocks(), blocks);
+            assert_eq!(allocator.validate(), Ok(()));
+            assert_eq!(allocator.alloc(layout), one);
+
+            allocator.dealloc(two, Layout::new::<[u8; 48]>());
+            assert!(allocator.realloc(two, layout, 64).is_null());
+            assert!(!allocator.find_ptr_block(two).used());
+            assert_eq!(allocator.validate(), Ok(()));
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn realloc_into_slack() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 20]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            // Free space follows, which growing past the slack would merge with
+            let two = allocator.alloc(Layout::new::<[u8; 48]>());
+            allocator.dealloc(two, Layout::new::<[u8; 48]>());
+            let usable = allocator.allocated_size(one).unwrap();
+            assert_eq!(usable, 24);
+
+            let block = allocator.find_ptr_block(one);
+            let header = block.0.read();
+            let next = allocator.next_header(&block).0.read();
+            let blocks = allocator.number_of_blocks();
+
+            assert_eq!(allocator.realloc(one, layout, usable), one);
+            let block = allocator.find_ptr_block(one);
+            assert_eq!(block.size(), header.size);
+            assert_eq!(block.0.read().offset, header.offset);
+            assert_eq!(allocator.next_header(&block).0.read().size, next.size);
+            assert_eq!(allocator.number_of_blocks(), blocks);
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn realloc_same_size() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 64]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            assert!(!one.is_null());
+
+            let two = allocator.alloc(layout);
+            assert!(!two.is_null());
+
+            let blocks = allocator.number_of_blocks();
+            let size = allocator.find_ptr_block(two).size();
+
+            assert_eq!(allocator.realloc(two, layout, layout.size()), two);
+            assert_eq!(allocator.number_of_blocks(), blocks);
+            let block = allocator.find_ptr_block(two);
+            assert!(block.used());
+            assert_eq!(block.size(), size);
+
+            allocator.dealloc(two, layout);
+            allocator.dealloc(one, layout);
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn checkpoint_restore() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 48]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            assert!(!one.is_null());
+            let two = allocator.alloc(layout);
+            assert!(!two.is_null());
+            allocator.dealloc(one, layout);
+
+            let checkpoint = allocator.checkpoint();
+
+            // The hole left by `one` isn't reused while the checkpoint is active
+            let three = allocator.alloc(layout);
+            assert!(three > two);
+            let four = allocator.alloc(layout);
+            assert!(!four.is_null());
+
+            allocator.restore(checkpoint);
+
+            assert!(allocator.find_ptr_block(two).used());
+            assert!(!allocator.find_ptr_block(three).used());
+            assert!(!allocator.find_ptr_block(four).used());
+
+            let five = allocator.alloc(layout);
+            assert_eq!(five, one);
+
+            allocator.dealloc(five, layout);
+            allocator.dealloc(two, layout);
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn heap_disjoint() {
+        let heap = Box::new([0xAAu8; 64]);
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 64]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            assert!(!one.is_null());
+            one.write_bytes(0x55, layout.size());
+
+            let arena =
+                allocator.buf_ptr().addr()..allocator.buf_ptr().addr() + page_size() * MAX_PAGES;
+            let heap_range = heap.as_ptr().addr()..heap.as_ptr().addr() + heap.len();
+            assert!(heap_range.end <= arena.start || heap_range.start >= arena.end);
+            assert!(heap.iter().all(|&byte| byte == 0xAA));
+
+            allocator.dealloc(one, layout);
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn malloc_disjoint() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 64]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            one.write_bytes(0x55, layout.size());
+
+            // Small mallocs are served from the program break, which grows towards higher addresses
+            let malloced = libc::malloc(64 * 1024).cast::<u8>();
+            assert!(!malloced.is_null());
+            malloced.write_bytes(0xAA, 64 * 1024);
+            let brk = libc::sbrk(0).cast::<u8>();
+
+            let arena = allocator.buf_ptr()..allocator.buf_ptr().add(page_size() * MAX_PAGES);
+            assert!(!arena.contains(&malloced) && !arena.contains(&malloced.add(64 * 1024 - 1)));
+            assert!(!arena.contains(&brk));
+            assert!((0..64).all(|i| one.add(i).read() == 0x55));
+
+            libc::free(malloced.cast::<c_void>());
+            allocator.dealloc(one, layout);
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn many_pages() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::array::<u8>(page_size()).unwrap();
+
+        unsafe {
+            let blocks: Vec<*mut u8> = (0..20).map(|_| allocator.alloc(layout)).collect();
+            assert!(blocks.iter().all(|block| !block.is_null()));
+            assert!(allocator.pages.load(Ordering::Relaxed) > 20);
+
+            // Every committed page is writable
+            blocks
+                .iter()
+                .for_each(|block| block.write_bytes(0xFF, layout.size()));
+            blocks
+                .iter()
+                .for_each(|&block| allocator.dealloc(block, layout));
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn reclaim() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::array::<u8>(page_size()).unwrap();
+
+        unsafe {
+            let blocks: Vec<*mut u8> = (0..4).map(|_| allocator.alloc(layout)).collect();
+            blocks
+                .iter()
+                .for_each(|block| block.write_bytes(0xFF, layout.size()));
+            assert!(allocator.pages.load(Ordering::Relaxed) >= 5);
+
+            blocks
+                .iter()
+                .for_each(|&block| allocator.dealloc(block, layout));
+            let pages = allocator.pages.load(Ordering::Relaxed) as usize;
+            allocator.reclaim();
+            assert_eq!(allocator.pages.load(Ordering::Relaxed), 1);
+            assert_eq!(allocator.number_of_blocks(), 1);
+
+            // None of the released pages are backed by memory anymore
+            let released = allocator.buf_ptr().add(page_size());
+            let mut residency = vec![0u8; pages - 1];
+            let status = libc::mincore(
+                released.cast::<c_void>(),
+                page_size() * (pages - 1),
+                residency.as_mut_ptr(),
+            );
+            assert_eq!(status, 0);
+            assert!(residency.iter().all(|&page| page & 1 == 0));
+
+            // The arena grows back into the released pages
+            let one = allocator.alloc(layout);
+            assert!(!one.is_null());
+            one.write_bytes(0xFF, layout.size());
+            allocator.dealloc(one, layout);
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn realloc_page_growth() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 64]>();
+        let boundary = page_size() - size_of::<Header>();
+        let beyond = boundary + page_size();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            assert!(!one.is_null());
+            (0..layout.size()).for_each(|i| one.add(i).write(i as u8));
+
+            // Fills the first page exactly
+            let grown = allocator.realloc(one, layout, boundary);
+            assert_eq!(grown, one);
+            assert_eq!(allocator.pages.load(Ordering::Relaxed), 1);
+            assert_eq!(allocator.number_of_blocks(), 1);
+            (0..layout.size()).for_each(|i| assert_eq!(grown.add(i).read(), i as u8));
+            (0..boundary).for_each(|i| grown.add(i).write(i as u8));
+
+            let grown = allocator.realloc(grown, Layout::array::<u8>(boundary).unwrap(), beyond);
+            assert_eq!(grown, one);
+            assert_eq!(allocator.pages.load(Ordering::Relaxed), 2);
+            (0..boundary).for_each(|i| assert_eq!(grown.add(i).read(), i as u8));
+            grown.write_bytes(0xFF, beyond);
+
+            let block = allocator.find_ptr_block(grown);
+            assert!(block.used());
+            assert_eq!(block.size(), beyond);
+
+            allocator.dealloc(grown, Layout::array::<u8>(beyond).unwrap());
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn calloc() {
+        let allocator = LinkedListAllocator::new();
+
+        assert!(allocator.calloc(usize::MAX, 2).is_null());
+
+        let ptr = allocator.calloc(1000, 8);
+        assert!(!ptr.is_null());
+        unsafe {
+            assert!((0..8000).all(|i| ptr.add(i).read() == 0));
+            allocator.dealloc(ptr, crate::calloc_layout(1000, 8).unwrap());
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn alloc_uninit() {
+        let allocator = LinkedListAllocator::new();
+
+        unsafe {
+            let array = allocator.alloc_uninit::<[u32; 4]>();
+            let elements = &mut *array.cast::<[MaybeUninit<u32>; 4]>();
+            for (i, element) in elements.iter_mut().enumerate() {
+                element.write(i as u32 * 10);
+            }
+            assert_eq!(array.cast::<[u32; 4]>().read(), [0, 10, 20, 30]);
+            allocator.dealloc(array.cast(), Layout::new::<[u32; 4]>());
+
+            let slice = &mut *allocator.alloc_uninit_slice::<u32>(4);
+            assert_eq!(slice.len(), 4);
+            for (i, element) in slice.iter_mut().enumerate() {
+                element.write(i as u32);
+            }
+            assert!(
+                slice
+                    .iter()
+                    .enumerate()
+                    .all(|(i, e)| e.assume_init_read() == i as u32)
+            );
+            allocator.dealloc(slice.as_mut_ptr().cast(), Layout::array::<u32>(4).unwrap());
+
+            assert!(allocator.alloc_uninit_slice::<u64>(usize::MAX).is_null());
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn try_grow_in_place() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 64]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            let two = allocator.alloc(layout);
+            let three = allocator.alloc(layout);
+
+            // Boxed in by `two`
+            assert!(!allocator.try_grow_in_place(one, layout, 128));
+            assert_eq!(allocator.allocated_size(one), Some(64));
+            assert!(allocator.try_grow_in_place(one, layout, 64));
+
+            allocator.dealloc(two, layout);
+            assert!(allocator.try_grow_in_place(one, layout, 128));
+            assert!(allocator.allocated_size(one).unwrap() >= 128);
+            assert_eq!(allocator.number_of_blocks(), 3);
+
+            // The top block grows into new pages
+            assert!(allocator.try_grow_in_place(three, layout, page_size() * 2));
+            three.add(page_size() * 2 - 1).write(1);
+
+            allocator.dealloc(one, Layout::new::<[u8; 128]>());
+            allocator.dealloc(three, Layout::array::<u8>(page_size() * 2).unwrap());
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn grow_threshold() {
         let allocator = LinkedListAllocator::new();
-        let layout = Layout::new::<[u8; 5000]>();
+        allocator.set_grow_threshold(0.5);
+        let tenth = Layout::array::<u8>(page_size() / 10).unwrap();
+
+        unsafe {
+            let blocks = (0..4).map(|_| allocator.alloc(tenth)).collect::<Vec<_>>();
+            assert!(allocator.used_ratio() < 0.5);
+            assert_eq!(allocator.pages.load(Ordering::Relaxed), 1);
+
+            // Crosses half of the first page
+            let crossing = allocator.alloc(Layout::array::<u8>(page_size() / 5).unwrap());
+            assert_eq!(allocator.pages.load(Ordering::Relaxed), 2);
+            assert!(allocator.used_ratio() < 0.5);
+
+            // The page went to the top block, so a block can span into it without committing another
+            allocator.set_grow_threshold(1.0);
+            let spanning = allocator.alloc(Layout::array::<u8>(page_size() / 2).unwrap());
+            assert!(!spanning.is_null());
+            spanning.add(page_size() / 2 - 1).write(1);
+            assert_eq!(allocator.pages.load(Ordering::Relaxed), 2);
+
+            blocks
+                .into_iter()
+                .for_each(|ptr| allocator.dealloc(ptr, tenth));
+            allocator.dealloc(crossing, Layout::array::<u8>(page_size() / 5).unwrap());
+            allocator.dealloc(spanning, Layout::array::<u8>(page_size() / 2).unwrap());
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn failed_page_growth() {
+        let source = MockSource {
+            map_budget: Cell::new(Some(1)),
+            ..Default::default()
+        };
+        let allocator = LinkedListAllocator::with_source(&source);
+        let layout = Layout::array::<u8>(page_size()).unwrap();
+
+        unsafe {
+            // Only the first page could be backed
+            assert!(allocator.alloc(layout).is_null());
+            source.map_budget.set(None);
+            assert_eq!(allocator.pages.load(Ordering::Relaxed), 1);
+
+            // Running out of arena fails the same way
+            let too_large = Layout::array::<u8>(page_size() * MAX_PAGES).unwrap();
+            assert!(allocator.alloc(too_large).is_null());
+
+            let one = allocator.alloc(layout);
+            assert!(!one.is_null());
+            allocator.dealloc(one, layout);
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn page_source_calls() {
+        let source = MockSource::default();
+        let allocator = LinkedListAllocator::with_source(&source);
+        let layout = Layout::array::<u8>(page_size()).unwrap();
 
         unsafe {
             let one = allocator.alloc(layout);
             assert!(!one.is_null());
+            one.write_bytes(0xFF, layout.size());
+
+            allocator.dealloc(one, layout);
+            allocator.reclaim();
+        }
+        allocator.free_allocator();
+
+        let arena = page_size() * MAX_PAGES;
+        assert_eq!(
+            *source.calls.borrow(),
+            [
+                Call::Reserve(arena),
+                Call::Map(0, page_size()),
+                Call::Map(page_size(), page_size()),
+                Call::Unmap(page_size(), page_size()),
+                Call::Release(0, arena),
+            ]
+        );
+    }
+
+    #[test]
+    fn ptr_range() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::array::<u8>(page_size()).unwrap();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            let range = allocator.as_ptr_range();
+            assert!(range.contains(&one));
+            assert!(one.add(layout.size()) <= range.end);
+            assert_eq!(
+                range.end.addr() - range.start.addr(),
+                page_size() * allocator.pages.load(Ordering::Relaxed) as usize
+            );
+
             allocator.dealloc(one, layout);
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[cfg(feature = "heap-check")]
+    #[test]
+    #[should_panic(expected = "written to after it was freed")]
+    fn use_after_free() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 64]>();
 
+        unsafe {
+            let one = allocator.alloc(layout);
             let two = allocator.alloc(layout);
             assert!(!two.is_null());
+
+            allocator.dealloc(one, layout);
+            one.add(8).write(0);
+
+            allocator.alloc(layout);
+        }
+    }
+
+    #[test]
+    fn realloc_merge_headers() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 64]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            let two = allocator.alloc(layout);
+            let three = allocator.alloc(layout);
+            let four = allocator.alloc(layout);
+            assert!(!four.is_null());
             allocator.dealloc(two, layout);
+            allocator.dealloc(three, layout);
+            let blocks = allocator.number_of_blocks();
+
+            // Exactly covers the two freed blocks and their headers
+            let merged_size = layout.size() + 2 * (size_of::<Header>() + layout.size());
+            let grown = allocator.realloc(one, layout, merged_size);
+            assert_eq!(grown, one);
+            assert_eq!(allocator.number_of_blocks(), blocks - 2);
+
+            let block = allocator.find_ptr_block(grown);
+            assert!(block.used());
+            assert!(!block.binned());
+            assert_eq!(block.get_offset(), 0);
+            assert_eq!(block.size(), merged_size);
+            assert_eq!(allocator.next_header(&block).get_data(), four);
+
+            for absorbed in [two, three] {
+                let header = absorbed.cast::<Header>().sub(1).read();
+                assert_eq!((header.size, header.offset), (0, 0));
+            }
+
+            allocator.dealloc(grown, Layout::array::<u8>(merged_size).unwrap());
+            allocator.dealloc(four, layout);
         }
+
         allocator.free_allocator();
     }
 
+    /// Hands out a reservation just below the top of the address space, which is never touched
+    struct WrappingSource;
+
+    impl PageSource for WrappingSource {
+        unsafe fn reserve(&self, _len: usize) -> *mut u8 {
+            ptr::without_provenance_mut(usize::MAX - page_size() + 1)
+        }
+
+        unsafe fn map(&self, hint: *mut u8, _len: usize) -> *mut u8 {
+            hint
+        }
+
+        unsafe fn unmap(&self, _ptr: *mut u8, _len: usize) {}
+
+        unsafe fn release(&self, _ptr: *mut u8, _len: usize) {}
+    }
+
     #[test]
-    fn zeroed() {
+    #[should_panic(expected = "wraps around the address space")]
+    fn wrapping_reservation() {
+        LinkedListAllocator::with_source(WrappingSource);
+    }
+
+    #[test]
+    fn try_with_source() {
+        let failure = LinkedListAllocator::try_with_source(WrappingSource).err();
+        assert_eq!(failure, Some(AllocFailure::ReserveFailed));
+
+        let source = MockSource {
+            map_budget: Cell::new(Some(0)),
+            ..Default::default()
+        };
+        let failure = LinkedListAllocator::try_with_source(&source).err();
+        assert_eq!(failure, Some(AllocFailure::CommitFailed));
+        // The reservation isn't leaked
+        assert!(matches!(
+            source.calls.borrow().last(),
+            Some(Call::Release(..))
+        ));
+    }
+
+    #[test]
+    fn wrapping_block() {
         let allocator = LinkedListAllocator::new();
         let layout = Layout::new::<[u8; 16]>();
 
         unsafe {
-            let one = allocator.alloc_zeroed(layout);
-            assert!(!one.is_null());
-
-            let two = allocator.alloc_zeroed(layout);
-            assert!(!two.is_null());
+            let one = allocator.alloc(layout);
+            let mut block = allocator.find_ptr_block(one);
+            let size = block.size();
 
-            let two_sum: u8 = (0..16).into_iter().map(|i| *(two.wrapping_add(i))).sum();
-            let one_sum: u8 = (0..16).into_iter().map(|i| *(one.wrapping_add(i))).sum();
-            assert_eq!(two_sum, 0);
-            assert_eq!(one_sum, 0);
+            // A size that runs past the end of the address space ends the walk instead of wrapping
+            block.set_size(usize::MAX - block.addr());
+            assert!(block.last_addr().is_none());
+            assert!(allocator.next_header(&block).is_null());
+            assert!(!allocator.grow_in_place(&mut block, usize::MAX, 1, None));
 
-            allocator.dealloc(two, layout);
+            block.set_size(size);
+            assert!(!allocator.grow_in_place(&mut block, usize::MAX - 7, 1, None));
             allocator.dealloc(one, layout);
         }
 
@@ -617,38 +2991,303 @@ mod test {
     }
 
     #[test]
-    fn realloc() {
+    fn no_empty_splits() {
         let allocator = LinkedListAllocator::new();
-        let layout = Layout::new::<[u8; 16]>();
+        let first = page_size() - size_of::<Header>();
+
+        unsafe {
+            // Exactly a header is left over, which can't hold any data
+            let whole = Layout::from_size_align(first - size_of::<Header>(), 8).unwrap();
+            let one = allocator.alloc(whole);
+            assert_eq!(allocator.number_of_blocks(), 1);
+            assert_eq!(allocator.find_ptr_block(one).size(), first);
+            allocator.dealloc(one, whole);
+
+            // A header and `MIN_BLOCK_SIZE` bytes is enough for a free block
+            let split = Layout::from_size_align(first - size_of::<Header>() - MIN_BLOCK_SIZE, 8);
+            let one = allocator.alloc(split.unwrap());
+            assert_eq!(allocator.number_of_blocks(), 2);
+            assert_eq!(
+                allocator.next_header(&allocator.find_ptr_block(one)).size(),
+                8
+            );
+
+            let mut block = allocator.first_block();
+            while !block.is_null() {
+                assert!(block.used() || block.size() >= MIN_BLOCK_SIZE);
+                block.set(&allocator.next_header(&block));
+            }
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn realloc_backward() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 48]>();
 
         unsafe {
             let one = allocator.alloc(layout);
-            assert!(!one.is_null());
+            let two = allocator.alloc(layout);
+            // Keeps `two` from growing forward
+            let three = allocator.alloc(layout);
+            (0..48).for_each(|i| two.add(i).write(i as u8));
+            allocator.dealloc(one, layout);
+
+            let moved = allocator.realloc(two, layout, 80);
+            assert_eq!(moved, one);
+            (0..48).for_each(|i| assert_eq!(moved.add(i).read(), i as u8));
+
+            // The merged block, what's left of it, `three` and the rest of the page
+            let merged = allocator.find_ptr_block(moved);
+            assert!(merged.used());
+            assert_eq!(merged.size(), 80);
+            let leftover = allocator.next_header(&merged);
+            assert!(!leftover.used());
+            assert_eq!(allocator.next_header(&leftover).get_data(), three);
+            assert_eq!(allocator.number_of_blocks(), 4);
+        }
 
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn realloc_backward_overlap() {
+        let allocator = LinkedListAllocator::new();
+        // Not a bin size, so the freed block stays mergeable
+        let front = Layout::new::<[u8; 24]>();
+        let layout = Layout::new::<[u8; 48]>();
+
+        unsafe {
+            let one = allocator.alloc(front);
             let two = allocator.alloc(layout);
-            assert!(!two.is_null());
+            let _three = allocator.alloc(layout);
+            (0..48).for_each(|i| two.add(i).write(i as u8 ^ 0xA5));
+            allocator.dealloc(one, front);
+
+            // Moves back by 40 bytes, less than the 48 being moved
+            let moved = allocator.realloc(two, layout, 80);
+            assert_eq!(moved, one);
+            assert!(two.addr() - moved.addr() < layout.size());
+            (0..48).for_each(|i| assert_eq!(moved.add(i).read(), i as u8 ^ 0xA5));
+            assert_eq!(allocator.validate(), Ok(()));
+        }
 
-            allocator.realloc(two, layout, 32);
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn realloc_both_sides() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 48]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            let two = allocator.alloc(layout);
+            let three = allocator.alloc(layout);
+            // Keeps `two` from growing into the rest of the page
+            let four = allocator.alloc(layout);
+            (0..48).for_each(|i| two.add(i).write(i as u8));
             allocator.dealloc(one, layout);
-            allocator.dealloc(two, Layout::new::<[u8; 32]>());
+            allocator.dealloc(three, layout);
+
+            // Neither neighbour alone is enough, together with `two` they span 176 bytes
+            let merged = allocator.realloc(two, layout, 160);
+            assert_eq!(merged, one);
+            (0..48).for_each(|i| assert_eq!(merged.add(i).read(), i as u8));
+
+            // The merged block, `four` and the rest of the page
+            let block = allocator.find_ptr_block(merged);
+            assert_eq!(block.size(), 176);
+            assert_eq!(allocator.next_header(&block).get_data(), four);
+            assert_eq!(allocator.number_of_blocks(), 3);
         }
 
         allocator.free_allocator();
     }
 
     #[test]
-    fn merge() {
+    fn realloc_moved_alignment() {
         let allocator = LinkedListAllocator::new();
-        let layout = Layout::new::<[u8; 2000]>();
+        let layout = Layout::from_size_align(48, 32).unwrap();
 
         unsafe {
             let one = allocator.alloc(layout);
-            assert!(!one.is_null());
+            assert!(one.addr().is_multiple_of(32));
+            (0..48).for_each(|i| one.add(i).write(i as u8));
+            // Blocks growth in place, and there's nothing free before the first block
+            let _two = allocator.alloc(Layout::new::<[u8; 48]>());
+
+            let moved = allocator.realloc(one, layout, 200);
+            assert_ne!(moved, one);
+            assert!(moved.addr().is_multiple_of(32));
+            (0..48).for_each(|i| assert_eq!(moved.add(i).read(), i as u8));
+            assert!(!allocator.first_block().used());
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn failed_realloc_keeps_block() {
+        let source = MockSource::default();
+        let allocator = LinkedListAllocator::with_source(&source);
+        let layout = Layout::new::<[u8; 48]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            (0..48).for_each(|i| one.add(i).write(i as u8));
+            let _two = allocator.alloc(layout);
+            let blocks = allocator.number_of_blocks();
+
+            // No page can be committed to grow or move it, and the arena can never hold this much
+            source.map_budget.set(Some(0));
+            assert!(allocator.realloc(one, layout, page_size() * 2).is_null());
+            assert!(
+                allocator
+                    .realloc(one, layout, page_size() * MAX_PAGES)
+                    .is_null()
+            );
+
+            assert!(allocator.find_ptr_block(one).used());
+            assert_eq!(allocator.number_of_blocks(), blocks);
+            (0..48).for_each(|i| assert_eq!(one.add(i).read(), i as u8));
             allocator.dealloc(one, layout);
+            assert!(!allocator.first_block().used());
+        }
 
-            let layout = Layout::new::<[u8; 3080]>();
-            let two = allocator.alloc(layout);
-            assert!(!two.is_null());
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn realloc_usable() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 48]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            assert_eq!(allocator.allocated_size(one), Some(48));
+
+            // Sizes are rounded up to whole words, so the block can hold more than was asked for
+            let (grown, usable) = allocator.realloc_usable(one, layout, 100);
+            assert!(usable >= 100);
+            assert_eq!(usable, allocator.find_ptr_block(grown).size());
+
+            // Shrinking keeps the block in place but frees its tail
+            let (shrunk, usable) = allocator.realloc_usable(grown, Layout::new::<[u8; 100]>(), 10);
+            assert_eq!(shrunk, grown);
+            assert!((10..100).contains(&usable));
+
+            let (failed, usable) =
+                allocator.realloc_usable(shrunk, Layout::new::<[u8; 10]>(), usize::MAX / 2);
+            assert!(failed.is_null());
+            assert_eq!(usable, 0);
+            assert_eq!(allocator.allocated_size(ptr::null_mut()), None);
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn alloc_at() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u64; 4]>();
+        let base = allocator.buf_ptr();
+
+        unsafe {
+            let placed = allocator.alloc_at(256, layout);
+            assert_eq!(placed, base.add(256));
+            // Taken, inside a header, or misaligned
+            assert!(allocator.alloc_at(256, layout).is_null());
+            assert!(allocator.alloc_at(8, layout).is_null());
+            assert!(
+                allocator
+                    .alloc_at(1024 + 8, Layout::from_size_align(8, 32).unwrap())
+                    .is_null()
+            );
+
+            // Just past the placed block, so its offset covers the gap
+            let after = allocator.alloc_at(256 + 32 + 24, layout);
+            assert_eq!(after, base.add(256 + 32 + 24));
+            assert!(allocator.find_ptr_block(after).used());
+
+            // Beyond the first page, which gets committed for it
+            let far = allocator.alloc_at(page_size() * 3, layout);
+            assert_eq!(far, base.add(page_size() * 3));
+            far.write_bytes(0xAB, 32);
+
+            let one = allocator.alloc(layout);
+            assert!(!one.is_null() && one != placed && one != after && one != far);
+            allocator.dealloc(placed, layout);
+            allocator.dealloc(after, layout);
+            allocator.dealloc(far, layout);
+            allocator.dealloc(one, layout);
+        }
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn leak_check() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 48]>();
+
+        allocator.leak_check(|| unsafe {
+            let one = allocator.alloc(layout);
+            allocator.dealloc(one, layout);
+        });
+        assert_eq!(allocator.used_bytes(), 0);
+
+        allocator.free_allocator();
+    }
+
+    #[test]
+    #[should_panic(expected = "leaked 48 bytes")]
+    fn leak_check_leaked() {
+        let allocator = LinkedListAllocator::new();
+        let layout = Layout::new::<[u8; 48]>();
+
+        allocator.leak_check(|| unsafe {
+            let _ = allocator.alloc(layout);
+        });
+    }
+
+    #[test]
+    fn os_page_size() {
+        let allocator = LinkedListAllocator::new();
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+
+        assert_eq!(allocator.page_size(), page_size);
+        assert_eq!(allocator.as_ptr_range().end, unsafe {
+            allocator.buf_ptr().add(page_size)
+        });
+        assert_eq!(
+            allocator.first_block().size(),
+            page_size - size_of::<Header>()
+        );
+
+        allocator.free_allocator();
+    }
+
+    #[cfg(all(feature = "zeroize", not(feature = "heap-check")))]
+    #[test]
+    fn zeroize() {
+        let allocator = LinkedListAllocator::new();
+
+        // A binned size, whose link to the next binned block is also cleared, and an unbinned one
+        for size in [32, 100] {
+            let layout = Layout::from_size_align(size, 8).unwrap();
+            unsafe {
+                let secret = allocator.alloc(layout);
+                secret.write_bytes(0xA5, size);
+                allocator.dealloc(secret, layout);
+
+                let reused = allocator.alloc(layout);
+                assert_eq!(reused, secret);
+                assert!((0..size).all(|i| reused.add(i).read() == 0));
+                allocator.dealloc(reused, layout);
+            }
         }
 
         allocator.free_allocator();
@@ -1,68 +1,914 @@
-use core::alloc::{self, AllocError, GlobalAlloc, Layout};
-use core::cmp::{self, max};
-use core::ffi::{self, c_void};
-use core::ptr::{self, NonNull, null, null_mut};
-use libc::{self, mmap, munmap, sbrk};
+use core::alloc::{self, GlobalAlloc, Layout};
+use core::cmp;
+use core::ffi::c_void;
+use core::fmt;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use libc::{self, MAP_FAILED, mmap, munmap};
 
 use lazy_static::lazy_static;
 
+use crate::{
+    layout_table::LayoutTable, linked_list_allocator::LinkedListAllocator,
+    page_source::ExternalMapping,
+};
+
 lazy_static! {
     pub static ref PAGE_SIZE: usize = page_size();
 }
 
-fn page_size() -> usize {
+/// The OS page size, which every mapping is rounded to
+pub fn page_size() -> usize {
     unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
 }
 
-pub struct YerbaAlloc;
+/// Stored at the start of every mapping, in front of the returned pointer
+#[repr(C)]
+pub(crate) struct MapHeader {
+    /// Length of the whole mapping, including the header
+    /// May exceed what was requested when the allocation was over-allocated to absorb growth
+    pub(crate) len: usize,
+}
+
+/// Distance from the start of a mapping to the data, keeping the data aligned to `align`
+pub(crate) fn header_offset(align: usize) -> usize {
+    cmp::max(size_of::<MapHeader>(), align)
+}
+
+/// Length of the mapping needed to hold `size` bytes of data aligned to `align`
+fn mapping_len(size: usize, align: usize) -> Option<usize> {
+    header_offset(align)
+        .checked_add(size)?
+        .checked_next_multiple_of(*PAGE_SIZE)
+}
+
+/// Recovers the header of the mapping holding `ptr`
+pub(crate) unsafe fn header_of(ptr: *mut u8, align: usize) -> *mut MapHeader {
+    unsafe { ptr.sub(header_offset(align)).cast::<MapHeader>() }
+}
+
+/// A `LinkedListAllocator` carving its blocks out of an existing mapping
+pub type SubAllocator = LinkedListAllocator<ExternalMapping>;
+
+/// Sub-allocates within a region mapped elsewhere, such as an mmap'd file or a shared memory segment
+/// Neither `dealloc` nor `free_allocator` unmap the region, it stays owned by whoever mapped it
+/// Panics if the region is smaller than a page
+///
+/// # Safety
+/// Same as `ExternalMapping::new`, the region's previous contents are overwritten
+pub unsafe fn from_mapping(base: *mut u8, len: usize) -> SubAllocator {
+    LinkedListAllocator::with_source(unsafe { ExternalMapping::new(base, len) })
+}
+
+/// Maps every allocation directly from the OS
+///
+/// Can be installed as the global allocator
+/// ```no_run
+/// use yerba::page_allocator::YerbaAlloc;
+///
+/// #[global_allocator]
+/// static ALLOCATOR: YerbaAlloc = YerbaAlloc::new();
+///
+/// fn main() {}
+/// ```
+pub struct YerbaAlloc {
+    /// Bit pattern of the `f32` growth factor applied by `realloc`
+    growth_factor: AtomicU32,
+    /// Address the next mapping has to be placed at, 0 to let the kernel choose
+    address_hint: AtomicUsize,
+    maps: AtomicUsize,
+    bytes_mapped: AtomicUsize,
+    remaps: AtomicUsize,
+    unmaps: AtomicUsize,
+    bytes_unmapped: AtomicUsize,
+    /// Layouts of live mappings, checked on dealloc in debug builds
+    pub(crate) layouts: LayoutTable,
+}
+
+/// Counts of the syscalls an allocator has made, since it was created or its stats were last reset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageStats {
+    /// Number of new mappings
+    pub maps: usize,
+    /// Total length of the new mappings
+    pub bytes_mapped: usize,
+    /// Number of mappings resized in the kernel, without a copy
+    pub remaps: usize,
+    /// Number of `munmap` calls, for whole mappings or their tails
+    pub unmaps: usize,
+    /// Total length unmapped
+    pub bytes_unmapped: usize,
+}
 
-#[global_allocator]
-pub static ALLOCATOR: YerbaAlloc = YerbaAlloc {};
+pub static ALLOCATOR: YerbaAlloc = YerbaAlloc::new();
 
 // static mut START: *mut Block = null_mut();
 // static mut TOP: *mut Block = unsafe { START }; // starts as start
 
-unsafe impl GlobalAlloc for YerbaAlloc {
-    unsafe fn alloc(&self, layout: alloc::Layout) -> *mut u8 {
-        let aligned_layout = match layout.align_to(cmp::max(layout.align(), *PAGE_SIZE)) {
-            Ok(layout) => layout.pad_to_align(),
-            Err(_) => return ptr::null_mut(),
+impl YerbaAlloc {
+    pub const fn new() -> Self {
+        YerbaAlloc {
+            growth_factor: AtomicU32::new(1.0f32.to_bits()),
+            address_hint: AtomicUsize::new(0),
+            maps: AtomicUsize::new(0),
+            bytes_mapped: AtomicUsize::new(0),
+            remaps: AtomicUsize::new(0),
+            unmaps: AtomicUsize::new(0),
+            bytes_unmapped: AtomicUsize::new(0),
+            layouts: LayoutTable::new(),
+        }
+    }
+
+    pub fn stats(&self) -> PageStats {
+        PageStats {
+            maps: self.maps.load(Ordering::Relaxed),
+            bytes_mapped: self.bytes_mapped.load(Ordering::Relaxed),
+            remaps: self.remaps.load(Ordering::Relaxed),
+            unmaps: self.unmaps.load(Ordering::Relaxed),
+            bytes_unmapped: self.bytes_unmapped.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Zeroes every counter reported by `stats`
+    pub fn reset_stats(&self) {
+        [
+            &self.maps,
+            &self.bytes_mapped,
+            &self.remaps,
+            &self.unmaps,
+            &self.bytes_unmapped,
+        ]
+        .iter()
+        .for_each(|counter| counter.store(0, Ordering::Relaxed));
+    }
+
+    /// Places new mappings at `addr`, each one right after the last, so tests see the same addresses every run
+    /// A mapping the kernel can't put at its address fails, rather than quietly landing somewhere else
+    /// `addr` must be page aligned, 0, the default, lets the kernel choose
+    pub fn set_address_hint(&self, addr: usize) {
+        self.address_hint.store(addr, Ordering::Relaxed);
+    }
+
+    pub fn address_hint(&self) -> usize {
+        self.address_hint.load(Ordering::Relaxed)
+    }
+
+    /// Makes `realloc` grow to at least `old_size * factor`, so repeated growth maps less often
+    /// The extra capacity is recorded in the mapping, and later reallocs that fit in it don't map at all
+    /// Factors below `1.0` are treated as `1.0`
+    pub fn set_growth_factor(&self, factor: f32) {
+        self.growth_factor
+            .store(factor.max(1.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn growth_factor(&self) -> f32 {
+        f32::from_bits(self.growth_factor.load(Ordering::Relaxed))
+    }
+
+    /// Allocates a zeroed array of `count` elements
+    /// Every allocation is a fresh anonymous mapping, which the kernel already zeroes, so no memset is done
+    pub fn alloc_array_zeroed<T>(&self, count: usize) -> Option<NonNull<[T]>> {
+        let layout = Layout::array::<T>(count).ok()?;
+        let ptr = NonNull::new(unsafe { self.alloc(layout) })?;
+
+        Some(NonNull::slice_from_raw_parts(ptr.cast::<T>(), count))
+    }
+
+    /// Allocates zeroed memory for `count` elements of `size` bytes, like C's `calloc`
+    /// Returns null if the total size is zero or overflows
+    /// Fresh mappings are already zeroed by the kernel, so no memset is done
+    pub fn calloc(&self, count: usize, size: usize) -> *mut u8 {
+        match crate::calloc_layout(count, size) {
+            Some(layout) => unsafe { self.alloc(layout) },
+            None => ptr::null_mut(),
+        }
+    }
+
+    /// Allocates like `alloc`, then asks the kernel to populate the mapping's pages up front
+    /// Saves the page faults of touching a large buffer for the first time, the hint may be ignored
+    ///
+    /// # Safety
+    /// Same as `GlobalAlloc::alloc`
+    #[cfg(target_os = "linux")]
+    pub unsafe fn alloc_prefetch(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.alloc(layout) };
+        if !ptr.is_null() {
+            unsafe {
+                let header = header_of(ptr, layout.align());
+                libc::madvise(header.cast::<c_void>(), (*header).len, libc::MADV_WILLNEED);
+            }
+        }
+
+        ptr
+    }
+
+    /// Allocates like `alloc`, but maps with `MAP_POPULATE` so every page is faulted in before returning
+    /// Suits real-time code that can't take a page fault mid-operation, `alloc` stays lazy
+    #[cfg(target_os = "linux")]
+    #[track_caller]
+    pub fn alloc_populated(&self, layout: Layout) -> *mut u8 {
+        self.alloc_mapping(layout, libc::MAP_POPULATE)
+    }
+
+    /// Maps a fresh allocation for `layout`, passing `flags` to `mmap` on top of the defaults
+    #[track_caller]
+    fn alloc_mapping(&self, layout: Layout, flags: libc::c_int) -> *mut u8 {
+        let align = crate::checked_align(layout.align());
+        let Some(len) = mapping_len(layout.size(), align) else {
+            return crate::error::alloc_error(layout);
         };
-        unsafe {
+
+        let base = self.map(len, align, flags);
+        if base.is_null() {
+            return crate::error::alloc_error(layout);
+        }
+
+        let ptr = unsafe { base.add(header_offset(align)) };
+        self.layouts.record(ptr, layout);
+        ptr
+    }
+
+    /// Maps `len` bytes starting at a multiple of `align` and records the length in the mapping's header
+    /// `mmap` only guarantees page alignment, so larger alignments map extra and trim the excess on either side
+    fn map(&self, len: usize, align: usize, flags: libc::c_int) -> *mut u8 {
+        let extra = align.saturating_sub(*PAGE_SIZE);
+        let Some(map_len) = len.checked_add(extra) else {
+            return ptr::null_mut();
+        };
+        let hint = self.address_hint();
+        let base = unsafe {
             mmap(
-                ptr::null_mut(),
-                aligned_layout.size(),
+                ptr::without_provenance_mut(hint),
+                map_len,
                 libc::PROT_READ | libc::PROT_WRITE,
-                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | flags,
                 -1,
                 0,
             )
+        };
+        if base == MAP_FAILED {
+            return ptr::null_mut();
+        }
+        if hint != 0 {
+            if base.addr() != hint {
+                unsafe { munmap(base, map_len) };
+                return ptr::null_mut();
+            }
+            self.address_hint.store(hint + map_len, Ordering::Relaxed);
         }
-        .cast::<u8>()
+
+        let lead = crate::padding_for(base.cast::<u8>(), align);
+        unsafe {
+            if lead > 0 {
+                munmap(base, lead);
+            }
+            if extra > lead {
+                munmap(base.byte_add(lead + len), extra - lead);
+            }
+        }
+        let base = unsafe { base.byte_add(lead) };
+        self.maps.fetch_add(1, Ordering::Relaxed);
+        self.bytes_mapped.fetch_add(len, Ordering::Relaxed);
+
+        unsafe { base.cast::<MapHeader>().write(MapHeader { len }) };
+        base.cast::<u8>()
     }
 
-    unsafe fn alloc_zeroed(&self, layout: alloc::Layout) -> *mut u8 {
-        let size = layout.size();
-        let address = unsafe { self.alloc(layout) };
-        (0..size).for_each(|i| unsafe { address.add(i).write(0) });
+    /// Unmaps `len` bytes at `ptr`, which may be a whole mapping or its tail
+    pub(crate) unsafe fn unmap(&self, ptr: *mut u8, len: usize) {
+        unsafe { munmap(ptr.cast::<c_void>(), len) };
+        self.unmaps.fetch_add(1, Ordering::Relaxed);
+        self.bytes_unmapped.fetch_add(len, Ordering::Relaxed);
+    }
 
-        address
+    /// Unmaps the whole pages of the mapping past the first `new_size` bytes of the allocation at `ptr`
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by this allocator with alignment `align`, and hold at least `new_size` bytes
+    pub(crate) unsafe fn unmap_tail(&self, ptr: *mut u8, align: usize, new_size: usize) {
+        unsafe {
+            let header = header_of(ptr, align);
+            let new_len = (header_offset(align) + new_size).next_multiple_of(*PAGE_SIZE);
+            if new_len < (*header).len {
+                let tail = header.byte_add(new_len);
+                self.unmap(tail.cast::<u8>(), (*header).len - new_len);
+                (*header).len = new_len;
+            }
+        }
+    }
+
+    /// Grows the allocation without ever moving it
+    /// Returns false, leaving the allocation untouched, if the pages following the mapping are taken
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by this allocator with `old_layout`
+    pub unsafe fn realloc_in_place(
+        &self,
+        ptr: *mut u8,
+        old_layout: alloc::Layout,
+        new_size: usize,
+    ) -> bool {
+        let align = old_layout.align();
+        let header = unsafe { header_of(ptr, align) };
+        let Some(required_len) = mapping_len(new_size, align) else {
+            return false;
+        };
+
+        let resized = required_len <= unsafe { (*header).len }
+            || !unsafe { self.remap(header, required_len, false) }.is_null();
+        if resized {
+            self.layouts.resize(ptr, old_layout, ptr, new_size);
+        }
+
+        resized
+    }
+
+    /// Resizes the allocation at `ptr` to `new_layout`, whose alignment may differ from `old_layout`'s
+    /// Where the data sits in a mapping depends on the alignment, so a change that moves it maps a fresh block and copies
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by this allocator with `old_layout`, and `new_layout` must not be zero sized
+    pub unsafe fn realloc_aligned(
+        &self,
+        ptr: *mut u8,
+        old_layout: alloc::Layout,
+        new_layout: alloc::Layout,
+    ) -> *mut u8 {
+        if header_offset(old_layout.align()) == header_offset(new_layout.align()) {
+            let new_ptr = unsafe { self.realloc(ptr, old_layout, new_layout.size()) };
+            self.layouts.set_align(new_ptr, new_layout.align());
+            return new_ptr;
+        }
+
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe {
+                let copy_size = cmp::min(old_layout.size(), new_layout.size());
+                ptr::copy_nonoverlapping(ptr, new_ptr, copy_size);
+                self.dealloc(ptr, old_layout);
+            }
+        }
+
+        new_ptr
+    }
+
+    /// Like `realloc`, but also returns whether the allocation moved
+    /// Mappings the kernel moves count as moved too, false if the reallocation failed
+    ///
+    /// # Safety
+    /// Same as `GlobalAlloc::realloc`
+    pub unsafe fn realloc_tracked(
+        &self,
+        ptr: *mut u8,
+        layout: alloc::Layout,
+        new_size: usize,
+    ) -> (*mut u8, bool) {
+        let new_ptr = unsafe { self.realloc(ptr, layout, new_size) };
+        (new_ptr, !new_ptr.is_null() && new_ptr != ptr)
     }
 
+    /// Moves the allocation at `ptr` into a mapping that fits `new_size` bytes
+    /// Reuses spare capacity or remaps in the kernel when it can, and only copies as a last resort
+    unsafe fn resize(&self, ptr: *mut u8, old_layout: alloc::Layout, new_size: usize) -> *mut u8 {
+        let align = old_layout.align();
+        let header = unsafe { header_of(ptr, align) };
+        let Some(required_len) = mapping_len(new_size, align) else {
+            return ptr::null_mut();
+        };
+
+        // Still fits in the capacity of the current mapping
+        if required_len <= unsafe { (*header).len } {
+            return ptr;
+        }
+
+        let grown_size = (old_layout.size() as f32 * self.growth_factor()) as usize;
+        let len = mapping_len(cmp::max(new_size, grown_size), align).unwrap_or(required_len);
+
+        // The kernel only keeps moved mappings page aligned
+        // It also picks where they go, so with an address hint the copy below maps at the hint instead
+        if align <= *PAGE_SIZE && self.address_hint() == 0 {
+            let remapped = unsafe { self.remap(header, len, true) };
+            if !remapped.is_null() {
+                return unsafe { remapped.add(header_offset(align)) };
+            }
+        }
+
+        let new_base = self.map(len, align, 0);
+        if new_base.is_null() {
+            return ptr::null_mut();
+        }
+        let new_ptr = unsafe { new_base.add(header_offset(align)) };
+
+        let copy_size = cmp::min(old_layout.size(), new_size);
+        unsafe { ptr::copy_nonoverlapping(ptr, new_ptr, copy_size) };
+
+        unsafe { self.unmap(header.cast::<u8>(), (*header).len) };
+
+        new_ptr
+    }
+
+    /// Resizes the mapping starting at `header` in the kernel, moving it if `may_move`
+    /// The contents, header included, are carried over without a userspace copy
+    /// Returns the new base of the mapping, or null if it couldn't be resized
+    #[cfg(target_os = "linux")]
+    unsafe fn remap(&self, header: *mut MapHeader, len: usize, may_move: bool) -> *mut u8 {
+        let flags = if may_move { libc::MREMAP_MAYMOVE } else { 0 };
+        let base = unsafe { libc::mremap(header.cast::<c_void>(), (*header).len, len, flags) };
+        if base == MAP_FAILED {
+            return ptr::null_mut();
+        }
+        self.remaps.fetch_add(1, Ordering::Relaxed);
+
+        unsafe { (*base.cast::<MapHeader>()).len = len };
+        base.cast::<u8>()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    unsafe fn remap(&self, _header: *mut MapHeader, _len: usize, _may_move: bool) -> *mut u8 {
+        ptr::null_mut()
+    }
+}
+
+impl Default for YerbaAlloc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for YerbaAlloc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stats = self.stats();
+        f.debug_struct("YerbaAlloc")
+            .field("used", &(stats.bytes_mapped - stats.bytes_unmapped))
+            .field("growth_factor", &self.growth_factor())
+            .field("stats", &stats)
+            .finish()
+    }
+}
+
+unsafe impl GlobalAlloc for YerbaAlloc {
+    #[track_caller]
+    unsafe fn alloc(&self, layout: alloc::Layout) -> *mut u8 {
+        self.alloc_mapping(layout, 0)
+    }
+
+    #[track_caller]
+    unsafe fn alloc_zeroed(&self, layout: alloc::Layout) -> *mut u8 {
+        // Every allocation is a fresh mapping, already zeroed by the kernel
+        unsafe { self.alloc(layout) }
+    }
+
+    #[track_caller]
     unsafe fn dealloc(&self, ptr: *mut u8, layout: alloc::Layout) {
-        let size = layout.size();
-        unsafe { munmap(ptr.cast::<c_void>(), size) };
+        self.layouts.release(ptr, layout);
+        unsafe {
+            let header = header_of(ptr, layout.align());
+            self.unmap(header.cast::<u8>(), (*header).len);
+        }
     }
 
     unsafe fn realloc(&self, ptr: *mut u8, old_layout: alloc::Layout, new_size: usize) -> *mut u8 {
-        let layout = Layout::from_size_align(new_size, old_layout.align())
-            .expect("Layout from alignment and new size failed");
-
-        let new_ptr = unsafe { self.alloc(layout) };
-        (0..layout.size()).for_each(|i| unsafe { new_ptr.add(i).write(ptr.add(i).read()) });
+        if new_size == old_layout.size() {
+            return ptr;
+        }
+        // Shrinking never moves the allocation, it only gives back the pages it no longer covers
+        if new_size < old_layout.size() {
+            unsafe { self.unmap_tail(ptr, old_layout.align(), new_size) };
+            self.layouts.resize(ptr, old_layout, ptr, new_size);
+            return ptr;
+        }
 
-        unsafe { munmap(ptr.cast::<c_void>(), old_layout.size()) };
+        let new_ptr = unsafe { self.resize(ptr, old_layout, new_size) };
+        self.layouts.resize(ptr, old_layout, new_ptr, new_size);
 
         new_ptr
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Every mapping is its own, the only shared state is atomic counters and the layout table
+    const _: () = {
+        crate::assert_send::<YerbaAlloc>();
+        crate::assert_sync::<YerbaAlloc>();
+    };
+
+    /// Simulates a `Vec` growing one element at a time, returning how many times its mapping changed
+    fn count_growths(allocator: &YerbaAlloc, elements: usize) -> usize {
+        let element = size_of::<u64>();
+        let mut layout = Layout::array::<u64>(1).unwrap();
+
+        unsafe {
+            let mut ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+            ptr.cast::<u64>().write(0);
+
+            for i in 1..elements {
+                ptr = allocator.realloc(ptr, layout, (i + 1) * element);
+                assert!(!ptr.is_null());
+
+                layout = Layout::array::<u64>(i + 1).unwrap();
+                ptr.cast::<u64>().add(i).write(i as u64);
+            }
+
+            (0..elements).for_each(|i| assert_eq!(ptr.cast::<u64>().add(i).read(), i as u64));
+            allocator.dealloc(ptr, layout);
+        }
+
+        let stats = allocator.stats();
+        stats.maps + stats.remaps - 1
+    }
+
+    #[test]
+    fn growth_factor() {
+        // 64 pages worth of elements
+        let elements = *PAGE_SIZE * 64 / size_of::<u64>();
+
+        let linear = YerbaAlloc::new();
+        let linear_growths = count_growths(&linear, elements);
+        assert!(linear_growths >= 63);
+
+        let doubling = YerbaAlloc::new();
+        doubling.set_growth_factor(2.0);
+        let doubling_growths = count_growths(&doubling, elements);
+        assert!(doubling_growths <= 7, "grew {doubling_growths} times");
+    }
+
+    #[test]
+    fn limits() {
+        assert!(page_size().is_power_of_two());
+        assert_eq!(*PAGE_SIZE, page_size());
+    }
+
+    #[test]
+    fn realloc_same_size() {
+        let allocator = YerbaAlloc::new();
+        let layout = Layout::new::<[u8; 64]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            assert!(!one.is_null());
+
+            let len = (*header_of(one, layout.align())).len;
+            assert_eq!(allocator.realloc(one, layout, layout.size()), one);
+            assert_eq!((*header_of(one, layout.align())).len, len);
+
+            allocator.dealloc(one, layout);
+        }
+    }
+
+    #[test]
+    fn realloc_tracked() {
+        let allocator = YerbaAlloc::new();
+        let layout = Layout::from_size_align(64, *PAGE_SIZE * 2).unwrap();
+
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            // Still fits in the first page of the mapping
+            assert_eq!(allocator.realloc_tracked(ptr, layout, 256), (ptr, false));
+
+            let grown = Layout::from_size_align(256, layout.align()).unwrap();
+            let (moved, was_moved) = allocator.realloc_tracked(ptr, grown, *PAGE_SIZE * 4);
+            assert!(was_moved);
+            assert_ne!(moved, ptr);
+
+            let layout = Layout::from_size_align(*PAGE_SIZE * 4, layout.align()).unwrap();
+            allocator.dealloc(moved, layout);
+        }
+    }
+
+    #[test]
+    fn realloc_copy() {
+        let allocator = YerbaAlloc::new();
+        let size = 1 << 20;
+        // Mappings aligned over a page can't be moved by the kernel, so growing copies them
+        let layout = Layout::from_size_align(size, *PAGE_SIZE * 2).unwrap();
+
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+            (0..size).for_each(|i| ptr.add(i).write((i * 31) as u8));
+
+            let grown = allocator.realloc(ptr, layout, size * 2);
+            assert!(!grown.is_null());
+            assert_ne!(grown, ptr);
+            assert!(grown.addr().is_multiple_of(layout.align()));
+            assert!((0..size).all(|i| grown.add(i).read() == (i * 31) as u8));
+
+            allocator.dealloc(
+                grown,
+                Layout::from_size_align(size * 2, layout.align()).unwrap(),
+            );
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn realloc_remaps() {
+        let allocator = YerbaAlloc::new();
+        let page = *PAGE_SIZE;
+        let layout = Layout::from_size_align(page * 4, 8).unwrap();
+
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+            (0..layout.size()).for_each(|i| ptr.add(i).write(i as u8));
+
+            let new_ptr = allocator.realloc(ptr, layout, page * 16);
+            assert!(!new_ptr.is_null());
+            (0..layout.size()).for_each(|i| assert_eq!(new_ptr.add(i).read(), i as u8));
+            new_ptr.add(page * 16 - 1).write(1);
+
+            let stats = allocator.stats();
+            assert_eq!((stats.maps, stats.remaps), (1, 1));
+
+            allocator.dealloc(new_ptr, Layout::from_size_align(page * 16, 8).unwrap());
+        }
+    }
+
+    #[test]
+    fn realloc_shrink_unmaps() {
+        let allocator = YerbaAlloc::new();
+        let page = *PAGE_SIZE;
+        // Leaves room for the header, so the mappings are exactly 16 and 4 pages long
+        let layout = Layout::from_size_align(page * 16 - 8, 8).unwrap();
+        let new_size = page * 4 - 8;
+
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+            (0..layout.size()).for_each(|i| ptr.add(i).write(i as u8));
+            assert_eq!((*header_of(ptr, layout.align())).len, page * 16);
+
+            assert_eq!(allocator.realloc(ptr, layout, new_size), ptr);
+            (0..new_size).for_each(|i| assert_eq!(ptr.add(i).read(), i as u8));
+            assert_eq!((*header_of(ptr, layout.align())).len, page * 4);
+
+            let stats = allocator.stats();
+            assert_eq!((stats.unmaps, stats.bytes_unmapped), (1, page * 12));
+
+            allocator.dealloc(ptr, Layout::from_size_align(new_size, 8).unwrap());
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn alloc_prefetch() {
+        let allocator = YerbaAlloc::new();
+        let page = *PAGE_SIZE;
+        let layout = Layout::from_size_align(page * 64, 8).unwrap();
+
+        unsafe {
+            let ptr = allocator.alloc_prefetch(layout);
+            assert!(!ptr.is_null());
+            assert!(
+                (0..layout.size())
+                    .step_by(page)
+                    .all(|i| ptr.add(i).read() == 0)
+            );
+            (0..layout.size()).for_each(|i| ptr.add(i).write(i as u8));
+            assert!((0..layout.size()).all(|i| ptr.add(i).read() == i as u8));
+            allocator.dealloc(ptr, layout);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn alloc_populated() {
+        let allocator = YerbaAlloc::new();
+        let page = *PAGE_SIZE;
+        let layout = Layout::from_size_align(page * 64, 8).unwrap();
+
+        let ptr = allocator.alloc_populated(layout);
+        assert!(!ptr.is_null());
+        unsafe {
+            // Every page is resident before anything has touched it
+            let header = header_of(ptr, layout.align());
+            let len = (*header).len;
+            let mut resident = vec![0u8; len / page];
+            assert_eq!(
+                libc::mincore(header.cast::<c_void>(), len, resident.as_mut_ptr().cast()),
+                0
+            );
+            assert!(resident.iter().all(|&page| page & 1 == 1));
+
+            assert!((0..layout.size()).all(|i| ptr.add(i).read() == 0));
+            allocator.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    fn address_hint() {
+        let allocator = YerbaAlloc::new();
+        let page = *PAGE_SIZE;
+        let base = 0x2000_0000_0000;
+        let layout = Layout::from_size_align(page * 2, 8).unwrap();
+        allocator.set_address_hint(base);
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            assert_eq!(one.addr(), base + header_offset(8));
+            let two = allocator.alloc(layout);
+            assert_eq!(two.addr(), base + page * 3 + header_offset(8));
+            assert_eq!(allocator.address_hint(), base + page * 6);
+
+            // Growing moves it to the hint too, rather than wherever the kernel would remap it
+            let grown = allocator.realloc(one, layout, page * 4);
+            assert_eq!(grown.addr(), base + page * 6 + header_offset(8));
+            let grown_layout = Layout::from_size_align(page * 4, 8).unwrap();
+
+            // Something else is already mapped where it would go
+            allocator.set_address_hint(base + page * 3);
+            assert!(allocator.alloc(layout).is_null());
+
+            allocator.dealloc(grown, grown_layout);
+            allocator.dealloc(two, layout);
+        }
+    }
+
+    #[test]
+    fn array_zeroed() {
+        let allocator = YerbaAlloc::new();
+        let count = 100_000;
+
+        let array = allocator.alloc_array_zeroed::<u64>(count).unwrap();
+        assert_eq!(array.len(), count);
+
+        unsafe {
+            assert!(array.as_ref().iter().all(|&element| element == 0));
+            allocator.dealloc(
+                array.cast::<u8>().as_ptr(),
+                Layout::array::<u64>(count).unwrap(),
+            );
+        }
+    }
+
+    #[test]
+    fn alloc_zeroed() {
+        let allocator = YerbaAlloc::new();
+        let layout = Layout::new::<[u64; 512]>();
+
+        unsafe {
+            let ptr = allocator.alloc_zeroed(layout);
+            assert!((0..layout.size()).all(|i| ptr.add(i).read() == 0));
+            allocator.dealloc(ptr, layout);
+
+            // A mapping that can't be made comes back null instead of being written through
+            let huge = Layout::from_size_align(isize::MAX as usize / 2, 8).unwrap();
+            assert!(allocator.alloc_zeroed(huge).is_null());
+        }
+    }
+
+    /// Maps `len` bytes at exactly `addr`, or returns false if anything is already there
+    #[cfg(target_os = "linux")]
+    fn map_at(addr: usize, len: usize) -> bool {
+        let ptr = unsafe {
+            mmap(
+                ptr::without_provenance_mut(addr),
+                len,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED_NOREPLACE,
+                -1,
+                0,
+            )
+        };
+
+        ptr != MAP_FAILED
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn realloc_in_place() {
+        let allocator = YerbaAlloc::new();
+        let page = *PAGE_SIZE;
+        let layout = Layout::from_size_align(page / 2, 8).unwrap();
+
+        unsafe {
+            // Free space after the mapping
+            let ptr = allocator.alloc(layout);
+            let end = header_of(ptr, layout.align()).addr() + page;
+            if map_at(end, page * 4) {
+                munmap(ptr::without_provenance_mut(end), page * 4);
+                assert!(allocator.realloc_in_place(ptr, layout, page * 3));
+                ptr.add(page * 3 - 1).write(1);
+                allocator.dealloc(ptr, Layout::from_size_align(page * 3, 8).unwrap());
+            } else {
+                allocator.dealloc(ptr, layout);
+            }
+
+            // Another mapping directly after it
+            let ptr = allocator.alloc(layout);
+            let end = header_of(ptr, layout.align()).addr() + page;
+            let blocker = map_at(end, page);
+            assert!(!allocator.realloc_in_place(ptr, layout, page * 3));
+            assert_eq!((*header_of(ptr, layout.align())).len, page);
+            allocator.dealloc(ptr, layout);
+            if blocker {
+                munmap(ptr::without_provenance_mut(end), page);
+            }
+        }
+    }
+
+    #[test]
+    fn calloc() {
+        let allocator = YerbaAlloc::new();
+
+        assert!(allocator.calloc(usize::MAX, 2).is_null());
+
+        let ptr = allocator.calloc(1000, 8);
+        assert!(!ptr.is_null());
+        unsafe {
+            assert!((0..8000).all(|i| ptr.add(i).read() == 0));
+            allocator.dealloc(ptr, crate::calloc_layout(1000, 8).unwrap());
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "allocated with size 64")]
+    fn mismatched_dealloc() {
+        let allocator = YerbaAlloc::new();
+
+        unsafe {
+            let one = allocator.alloc(Layout::new::<[u8; 64]>());
+            allocator.dealloc(one, Layout::new::<[u8; 32]>());
+        }
+    }
+
+    #[test]
+    fn stats_counters() {
+        let allocator = YerbaAlloc::new();
+        let page = *PAGE_SIZE;
+        let layouts = [1, page, page * 3].map(|size| Layout::from_size_align(size, 8).unwrap());
+        let lens = layouts.map(|layout| mapping_len(layout.size(), layout.align()).unwrap());
+
+        unsafe {
+            let ptrs = layouts.map(|layout| allocator.alloc(layout));
+            allocator.dealloc(ptrs[0], layouts[0]);
+            allocator.dealloc(ptrs[1], layouts[1]);
+
+            assert_eq!(
+                allocator.stats(),
+                PageStats {
+                    maps: 3,
+                    bytes_mapped: lens.iter().sum(),
+                    remaps: 0,
+                    unmaps: 2,
+                    bytes_unmapped: lens[0] + lens[1],
+                }
+            );
+
+            allocator.reset_stats();
+            allocator.dealloc(ptrs[2], layouts[2]);
+            assert_eq!(
+                allocator.stats(),
+                PageStats {
+                    maps: 0,
+                    bytes_mapped: 0,
+                    remaps: 0,
+                    unmaps: 1,
+                    bytes_unmapped: lens[2],
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn sub_allocate_mapping() {
+        let len = 4 * *PAGE_SIZE;
+        unsafe {
+            let base = mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_ANONYMOUS | libc::MAP_PRIVATE,
+                -1,
+                0,
+            );
+            assert_ne!(base, MAP_FAILED);
+            let base = base.cast::<u8>();
+
+            let allocator = from_mapping(base, len);
+            let layout = Layout::new::<[u8; 1024]>();
+            let ptrs = (0..8).map(|_| allocator.alloc(layout)).collect::<Vec<_>>();
+            for (i, &ptr) in ptrs.iter().enumerate() {
+                assert!((base..base.add(len)).contains(&ptr));
+                ptr.write_bytes(i as u8, layout.size());
+            }
+            // The region is only four pages long, the blocks' headers leave less than the rest of it free
+            let rest = Layout::from_size_align(len - ptrs.len() * layout.size(), 1).unwrap();
+            assert!(allocator.alloc(rest).is_null());
+
+            for ptr in ptrs {
+                allocator.dealloc(ptr, layout);
+            }
+            allocator.free_allocator();
+
+            // Still mapped after the allocator is gone
+            base.add(len - 1).write(1);
+            assert_eq!(munmap(base.cast::<c_void>(), len), 0);
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use core::{ffi::c_void, ptr};
+
+use libc::{MADV_DONTNEED, MAP_ANONYMOUS, MAP_FAILED, MAP_NORESERVE, MAP_PRIVATE, PROT_NONE};
+
+/// Where an arena gets its memory from
+/// The arena reserves its whole address range once, then backs it a page at a time as it grows
+pub trait PageSource {
+    /// Reserves `len` bytes of address space without backing them
+    /// Returns null if the space couldn't be reserved
+    ///
+    /// # Safety
+    /// `len` must be a non-zero multiple of the page size
+    unsafe fn reserve(&self, len: usize) -> *mut u8;
+
+    /// Backs the `len` bytes at `hint` with readable and writable memory
+    /// Returns `hint`, or null if the memory couldn't be backed
+    ///
+    /// # Safety
+    /// The range must lie within a region returned by `reserve`
+    unsafe fn map(&self, hint: *mut u8, len: usize) -> *mut u8;
+
+    /// Drops the memory backing the `len` bytes at `ptr`, the address space stays reserved
+    ///
+    /// # Safety
+    /// The range must have been backed by `map`, and nothing may access it until it's mapped again
+    unsafe fn unmap(&self, ptr: *mut u8, len: usize);
+
+    /// Gives back a whole reservation
+    ///
+    /// # Safety
+    /// `ptr` and `len` must describe a region returned by `reserve`, which isn't used afterwards
+    unsafe fn release(&self, ptr: *mut u8, len: usize);
+}
+
+/// Anonymous private mappings from the OS
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LibcMmap;
+
+impl PageSource for LibcMmap {
+    unsafe fn reserve(&self, len: usize) -> *mut u8 {
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                PROT_NONE,
+                MAP_ANONYMOUS | MAP_PRIVATE | MAP_NORESERVE,
+                -1,
+                0,
+            )
+        };
+        if base == MAP_FAILED {
+            return ptr::null_mut();
+        }
+
+        base.cast::<u8>()
+    }
+
+    unsafe fn map(&self, hint: *mut u8, len: usize) -> *mut u8 {
+        let protected = unsafe {
+            libc::mprotect(
+                hint.cast::<c_void>(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+            )
+        };
+        if protected != 0 {
+            return ptr::null_mut();
+        }
+
+        hint
+    }
+
+    unsafe fn unmap(&self, ptr: *mut u8, len: usize) {
+        unsafe {
+            libc::madvise(ptr.cast::<c_void>(), len, MADV_DONTNEED);
+            libc::mprotect(ptr.cast::<c_void>(), len, PROT_NONE);
+        }
+    }
+
+    unsafe fn release(&self, ptr: *mut u8, len: usize) {
+        unsafe { libc::munmap(ptr.cast::<c_void>(), len) };
+    }
+}
+
+/// Anonymous private mappings reserved at a fixed address, so an arena lands in the same place every run
+/// Lets tests assert on pointers, reserving fails if the kernel won't place the arena at the address
+#[derive(Debug, Clone, Copy)]
+pub struct FixedAddress {
+    base: usize,
+}
+
+impl FixedAddress {
+    /// `base` must be page aligned
+    pub fn new(base: usize) -> Self {
+        Self { base }
+    }
+}
+
+impl PageSource for FixedAddress {
+    unsafe fn reserve(&self, len: usize) -> *mut u8 {
+        let base = unsafe {
+            libc::mmap(
+                ptr::without_provenance_mut(self.base),
+                len,
+                PROT_NONE,
+                MAP_ANONYMOUS | MAP_PRIVATE | MAP_NORESERVE,
+                -1,
+                0,
+            )
+        };
+        if base == MAP_FAILED {
+            return ptr::null_mut();
+        }
+        if base.addr() != self.base {
+            unsafe { libc::munmap(base, len) };
+            return ptr::null_mut();
+        }
+
+        base.cast::<u8>()
+    }
+
+    unsafe fn map(&self, hint: *mut u8, len: usize) -> *mut u8 {
+        unsafe { LibcMmap.map(hint, len) }
+    }
+
+    unsafe fn unmap(&self, ptr: *mut u8, len: usize) {
+        unsafe { LibcMmap.unmap(ptr, len) }
+    }
+
+    unsafe fn release(&self, ptr: *mut u8, len: usize) {
+        unsafe { LibcMmap.release(ptr, len) }
+    }
+}
+
+/// A region mapped by someone else, such as an mmap'd file or a shared memory segment
+/// Its pages are handed out as the arena grows into them and are never unmapped
+#[derive(Debug, Clone, Copy)]
+pub struct ExternalMapping {
+    base: *mut u8,
+    len: usize,
+}
+
+impl ExternalMapping {
+    /// # Safety
+    /// `base` must be page aligned and point to `len` readable and writable bytes,
+    /// which stay mapped for as long as anything allocated from them is in use
+    pub unsafe fn new(base: *mut u8, len: usize) -> Self {
+        Self { base, len }
+    }
+}
+
+impl PageSource for ExternalMapping {
+    /// The region is already mapped, the part of the reservation past its end is never backed
+    unsafe fn reserve(&self, _len: usize) -> *mut u8 {
+        self.base
+    }
+
+    unsafe fn map(&self, hint: *mut u8, len: usize) -> *mut u8 {
+        let end = self.base.addr() + self.len;
+        if hint.addr() < self.base.addr() || hint.addr() + len > end {
+            return ptr::null_mut();
+        }
+
+        hint
+    }
+
+    unsafe fn unmap(&self, _ptr: *mut u8, _len: usize) {}
+
+    unsafe fn release(&self, _ptr: *mut u8, _len: usize) {}
+}
+
+impl<S: PageSource> PageSource for &S {
+    unsafe fn reserve(&self, len: usize) -> *mut u8 {
+        unsafe { (**self).reserve(len) }
+    }
+
+    unsafe fn map(&self, hint: *mut u8, len: usize) -> *mut u8 {
+        unsafe { (**self).map(hint, len) }
+    }
+
+    unsafe fn unmap(&self, ptr: *mut u8, len: usize) {
+        unsafe { (**self).unmap(ptr, len) }
+    }
+
+    unsafe fn release(&self, ptr: *mut u8, len: usize) {
+        unsafe { (**self).release(ptr, len) }
+    }
+}
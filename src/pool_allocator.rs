@@ -0,0 +1,167 @@
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+#[repr(C, align(64))]
+struct Buffers<const SIZE: usize, const COUNT: usize>([[u8; SIZE]; COUNT]);
+
+/// A fixed set of `COUNT` buffers of `SIZE` bytes, carved out up front and reused
+/// Buffers are handed out as `PooledBuffer` guards that return them to the pool when dropped,
+/// rather than as raw pointers that have to be freed by hand
+/// Suits pools of network or IO buffers, where the number in flight is bounded
+pub struct PoolAllocator<const SIZE: usize, const COUNT: usize> {
+    buffers: UnsafeCell<Buffers<SIZE, COUNT>>,
+    taken: [AtomicBool; COUNT],
+}
+
+// A buffer is only reachable through the guard that claimed its flag, so no two threads share one
+unsafe impl<const SIZE: usize, const COUNT: usize> Sync for PoolAllocator<SIZE, COUNT> {}
+
+impl<const SIZE: usize, const COUNT: usize> PoolAllocator<SIZE, COUNT> {
+    /// Number of bytes in each buffer
+    pub const BUFFER_SIZE: usize = SIZE;
+    /// Number of buffers in the pool
+    pub const CAPACITY: usize = COUNT;
+
+    pub const fn new() -> Self {
+        PoolAllocator {
+            buffers: UnsafeCell::new(Buffers([[0; SIZE]; COUNT])),
+            taken: [const { AtomicBool::new(false) }; COUNT],
+        }
+    }
+
+    /// Takes a free buffer, None if they're all in use
+    /// Its contents are whatever the last holder left in it, unless the `zeroize` feature cleared them
+    pub fn try_acquire(&self) -> Option<PooledBuffer<'_, SIZE, COUNT>> {
+        let index = self.taken.iter().position(|taken| {
+            taken
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        })?;
+
+        Some(PooledBuffer { pool: self, index })
+    }
+
+    /// Takes a free buffer, spinning until another thread returns one if they're all in use
+    pub fn acquire(&self) -> PooledBuffer<'_, SIZE, COUNT> {
+        loop {
+            if let Some(buffer) = self.try_acquire() {
+                return buffer;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Number of buffers that aren't in use
+    pub fn available(&self) -> usize {
+        self.taken
+            .iter()
+            .filter(|taken| !taken.load(Ordering::Relaxed))
+            .count()
+    }
+
+    fn buffer_ptr(&self, index: usize) -> *mut [u8; SIZE] {
+        unsafe { self.buffers.get().cast::<[u8; SIZE]>().add(index) }
+    }
+}
+
+impl<const SIZE: usize, const COUNT: usize> Default for PoolAllocator<SIZE, COUNT> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SIZE: usize, const COUNT: usize> fmt::Debug for PoolAllocator<SIZE, COUNT> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoolAllocator")
+            .field("buffer_size", &SIZE)
+            .field("capacity", &COUNT)
+            .field("available", &self.available())
+            .finish()
+    }
+}
+
+/// A buffer taken from a `PoolAllocator`, returned to it when dropped
+pub struct PooledBuffer<'a, const SIZE: usize, const COUNT: usize> {
+    pool: &'a PoolAllocator<SIZE, COUNT>,
+    index: usize,
+}
+
+impl<const SIZE: usize, const COUNT: usize> Deref for PooledBuffer<'_, SIZE, COUNT> {
+    type Target = [u8; SIZE];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.pool.buffer_ptr(self.index) }
+    }
+}
+
+impl<const SIZE: usize, const COUNT: usize> DerefMut for PooledBuffer<'_, SIZE, COUNT> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.pool.buffer_ptr(self.index) }
+    }
+}
+
+impl<const SIZE: usize, const COUNT: usize> Drop for PooledBuffer<'_, SIZE, COUNT> {
+    fn drop(&mut self) {
+        unsafe { crate::zeroize(self.pool.buffer_ptr(self.index).cast(), SIZE) };
+        self.pool.taken[self.index].store(false, Ordering::Release);
+    }
+}
+
+impl<const SIZE: usize, const COUNT: usize> fmt::Debug for PooledBuffer<'_, SIZE, COUNT> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledBuffer")
+            .field("index", &self.index)
+            .field("size", &SIZE)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Buffers are claimed with atomic flags, so a pool can be shared between threads
+    const _: () = {
+        crate::assert_send::<PoolAllocator<64, 4>>();
+        crate::assert_sync::<PoolAllocator<64, 4>>();
+    };
+
+    #[test]
+    fn acquire_all() {
+        let pool = PoolAllocator::<64, 4>::new();
+
+        let mut buffers = (0..4)
+            .map(|i| {
+                let mut buffer = pool.try_acquire().unwrap();
+                buffer.fill(i);
+                buffer
+            })
+            .collect::<Vec<_>>();
+        assert!(pool.try_acquire().is_none());
+        assert_eq!(pool.available(), 0);
+        for (i, buffer) in buffers.iter().enumerate() {
+            assert!(buffer.iter().all(|&byte| byte == i as u8));
+        }
+
+        let returned = buffers.remove(1).index;
+        assert_eq!(pool.available(), 1);
+        assert_eq!(pool.try_acquire().unwrap().index, returned);
+    }
+
+    #[test]
+    fn blocking_acquire() {
+        let pool = PoolAllocator::<64, 1>::new();
+        let held = pool.acquire();
+
+        std::thread::scope(|scope| {
+            let waiter = scope.spawn(|| pool.acquire().index);
+            drop(held);
+            assert_eq!(waiter.join().unwrap(), 0);
+        });
+        assert_eq!(pool.available(), 1);
+    }
+}
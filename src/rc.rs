@@ -0,0 +1,138 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cell::Cell,
+    fmt,
+    marker::PhantomData,
+    ops::Deref,
+    ptr::NonNull,
+};
+
+/// The count and the value, allocated together in one block
+struct RcBox<T> {
+    count: Cell<usize>,
+    value: T,
+}
+
+/// Shares a `T` allocated from a borrowed allocator, like `Rc` but without the nightly `Allocator` trait
+/// The count isn't atomic, so it stays on the thread that made it
+/// The value is dropped and its block freed when the last clone is dropped
+pub struct YerbaRc<'a, T, A: GlobalAlloc> {
+    ptr: NonNull<RcBox<T>>,
+    allocator: &'a A,
+    _marker: PhantomData<RcBox<T>>,
+}
+
+impl<'a, T, A: GlobalAlloc> YerbaRc<'a, T, A> {
+    /// Moves `value` into a block from `allocator`, None if it can't be allocated
+    ///
+    /// # Safety
+    /// `allocator` must keep the block valid until the last clone frees it, it can't be reset or lapped before then
+    pub unsafe fn new_in(value: T, allocator: &'a A) -> Option<Self> {
+        let ptr = NonNull::new(unsafe { allocator.alloc(Layout::new::<RcBox<T>>()) })?.cast();
+        unsafe {
+            ptr.write(RcBox {
+                count: Cell::new(1),
+                value,
+            })
+        };
+
+        Some(YerbaRc {
+            ptr,
+            allocator,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Number of `YerbaRc`s sharing the value, this one included
+    pub fn count(this: &Self) -> usize {
+        this.inner().count.get()
+    }
+
+    /// Whether both point to the same value
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.ptr == other.ptr
+    }
+
+    /// The allocator the value lives in
+    pub fn allocator(this: &Self) -> &'a A {
+        this.allocator
+    }
+
+    fn inner(&self) -> &RcBox<T> {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T, A: GlobalAlloc> Clone for YerbaRc<'_, T, A> {
+    fn clone(&self) -> Self {
+        let count = &self.inner().count;
+        count.set(count.get() + 1);
+
+        YerbaRc {
+            ptr: self.ptr,
+            allocator: self.allocator,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, A: GlobalAlloc> Deref for YerbaRc<'_, T, A> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner().value
+    }
+}
+
+impl<T, A: GlobalAlloc> Drop for YerbaRc<'_, T, A> {
+    fn drop(&mut self) {
+        let count = &self.inner().count;
+        count.set(count.get() - 1);
+        if count.get() != 0 {
+            return;
+        }
+
+        unsafe {
+            self.ptr.drop_in_place();
+            self.allocator
+                .dealloc(self.ptr.as_ptr().cast(), Layout::new::<RcBox<T>>());
+        }
+    }
+}
+
+impl<T: fmt::Debug, A: GlobalAlloc> fmt::Debug for YerbaRc<'_, T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::page_allocator::YerbaAlloc;
+
+    // The count isn't atomic, so clones can't be shared or sent between threads
+    crate::assert_not_impl!(YerbaRc<'static, u64, YerbaAlloc>, Send);
+    crate::assert_not_impl!(YerbaRc<'static, u64, YerbaAlloc>, Sync);
+
+    #[test]
+    fn last_drop_frees() {
+        let allocator = YerbaAlloc::new();
+
+        let rc = unsafe { YerbaRc::new_in([7u8; 32], &allocator) }.unwrap();
+        let one = rc.clone();
+        let two = one.clone();
+        assert_eq!(YerbaRc::count(&rc), 3);
+        assert!(YerbaRc::ptr_eq(&rc, &two));
+        assert_eq!(two[31], 7);
+
+        drop(one);
+        drop(two);
+        assert_eq!(YerbaRc::count(&rc), 1);
+        assert_eq!(allocator.stats().unmaps, 0);
+
+        drop(rc);
+        assert_eq!(allocator.stats().maps, 1);
+        assert_eq!(allocator.stats().unmaps, 1);
+    }
+}
@@ -0,0 +1,197 @@
+use core::{
+    alloc::Layout,
+    cell::UnsafeCell,
+    cmp, fmt,
+    ops::Range,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+const BUF_SIZE: usize = 4096;
+
+#[repr(C, align(64))]
+struct Buffer([u8; BUF_SIZE]);
+
+/// Bumps allocations upwards through a fixed buffer, wrapping back to the front once the end is reached
+/// Wrapping overwrites the oldest allocations, so blocks are only valid until the head laps them
+/// Suited to per-frame scratch space or log lines, there's no per-object free
+/// It isn't a `GlobalAlloc`, whose blocks have to stay valid until they're freed, so it can't back `Box` or `Vec`
+pub struct RingAllocator {
+    buf: UnsafeCell<Buffer>,
+    head: AtomicUsize,
+}
+
+// Regions are reserved by atomically moving the head, so concurrent allocations never overlap
+// Overlap with blocks from an earlier lap is part of the contract
+unsafe impl Sync for RingAllocator {}
+
+impl RingAllocator {
+    /// Number of bytes in the ring buffer
+    pub const CAPACITY: usize = BUF_SIZE;
+
+    pub const fn new() -> Self {
+        RingAllocator {
+            buf: UnsafeCell::new(Buffer([0; BUF_SIZE])),
+            head: AtomicUsize::new(0),
+        }
+    }
+
+    /// Moves the head back to the front of the buffer, every block is overwritten by later allocations
+    ///
+    /// # Safety
+    /// Blocks allocated before the reset must not be used afterwards
+    pub unsafe fn reset(&self) {
+        self.head.store(0, Ordering::Release);
+    }
+
+    /// The whole ring buffer, every block this allocator hands out lies within it
+    pub fn as_ptr_range(&self) -> Range<*mut u8> {
+        let start = self.buf_ptr();
+        start..unsafe { start.add(BUF_SIZE) }
+    }
+
+    /// Places a block at the head, wrapping to the front of the buffer if it doesn't fit before the end
+    /// Returns null if the block is larger than the whole buffer
+    ///
+    /// # Safety
+    /// The block is only valid until the head laps it, later allocations may overwrite it after that
+    /// Nothing may read or write it once they have
+    pub unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let size = layout.size();
+        let align = crate::checked_align(layout.align());
+
+        let mut start = 0;
+        let placed = self
+            .head
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |head| {
+                start = self.place(head, size, align)?;
+                Some(start + size)
+            });
+        if placed.is_err() {
+            return ptr::null_mut();
+        }
+
+        unsafe { self.buf_ptr().add(start) }
+    }
+
+    /// Always moves the block to the head
+    /// The new block may wrap over the old one, so the data is moved rather than copied
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by this allocator with `layout` and not been lapped yet,
+    /// the new block is only valid until the head laps it
+    pub unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let Some(new_layout) = crate::realloc_layout(layout, new_size) else {
+            return ptr::null_mut();
+        };
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe { ptr::copy(ptr, new_ptr, cmp::min(layout.size(), new_size)) };
+        }
+
+        new_ptr
+    }
+
+    fn buf_ptr(&self) -> *mut u8 {
+        self.buf.get().cast()
+    }
+
+    /// Offset of a block of `size` bytes placed at or after `head`, wrapping to the front if it doesn't fit
+    fn place(&self, head: usize, size: usize, align: usize) -> Option<usize> {
+        [head, 0].into_iter().find_map(|from| {
+            let padding = crate::padding_for(self.buf_ptr().wrapping_add(from), align);
+            let start = from.checked_add(padding)?;
+            (start.checked_add(size)? <= BUF_SIZE).then_some(start)
+        })
+    }
+}
+
+impl Default for RingAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for RingAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RingAllocator")
+            .field("capacity", &BUF_SIZE)
+            .field("head", &self.head.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Blocks are placed by atomically moving the head
+    const _: () = {
+        crate::assert_send::<RingAllocator>();
+        crate::assert_sync::<RingAllocator>();
+    };
+
+    #[test]
+    fn wraparound() {
+        let allocator = RingAllocator::new();
+        let layout = Layout::new::<[u8; 1024]>();
+
+        unsafe {
+            let first_lap = (0..4).map(|_| allocator.alloc(layout)).collect::<Vec<_>>();
+            assert!(first_lap.iter().all(|ptr| !ptr.is_null()));
+            assert_eq!(first_lap[0], allocator.as_ptr_range().start);
+
+            // The buffer is full, so the next lap reuses the same addresses
+            let second_lap = (0..4).map(|_| allocator.alloc(layout)).collect::<Vec<_>>();
+            assert_eq!(first_lap, second_lap);
+
+            let too_large = allocator.alloc(Layout::new::<[u8; BUF_SIZE + 1]>());
+            assert!(too_large.is_null());
+        }
+    }
+
+    #[test]
+    fn wrap_skips_tail() {
+        let allocator = RingAllocator::new();
+
+        unsafe {
+            let one = allocator.alloc(Layout::new::<[u8; 3000]>());
+            // Doesn't fit in the remaining 1096 bytes, so it wraps over `one`
+            let two = allocator.alloc(Layout::from_size_align(2000, 8).unwrap());
+            assert_eq!(two, one);
+
+            let three = allocator.alloc(Layout::from_size_align(8, 64).unwrap());
+            assert!(three.addr().is_multiple_of(64));
+            assert!(three >= two.add(2000));
+        }
+    }
+
+    #[test]
+    fn realloc_overflow() {
+        let allocator = RingAllocator::new();
+        let layout = Layout::new::<[u8; 64]>();
+
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            ptr.write_bytes(0xAB, 64);
+            // Sizes whose end overflows fail, leaving the block intact
+            for new_size in [usize::MAX, usize::MAX - 64, isize::MAX as usize] {
+                assert!(allocator.realloc(ptr, layout, new_size).is_null());
+            }
+            assert!((0..64).all(|i| ptr.add(i).read() == 0xAB));
+        }
+    }
+
+    #[test]
+    fn reset() {
+        let allocator = RingAllocator::new();
+        let layout = Layout::new::<[u8; 16]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            assert_ne!(allocator.alloc(layout), one);
+            allocator.reset();
+            assert_eq!(allocator.alloc(layout), one);
+        }
+    }
+}
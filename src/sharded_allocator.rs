@@ -0,0 +1,334 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cell::Cell,
+    fmt, hint,
+    ops::Deref,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use crate::{error::AllocFailure, linked_list_allocator::LinkedListAllocator};
+
+/// A `LinkedListAllocator` behind a spin lock
+struct Shard {
+    locked: AtomicBool,
+    heap: LinkedListAllocator,
+}
+
+struct ShardGuard<'a>(&'a Shard);
+
+impl Shard {
+    fn try_lock(&self) -> Option<ShardGuard<'_>> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+            // Built lazily, a guard made for a failed attempt would unlock the shard when dropped
+            .then(|| ShardGuard(self))
+    }
+
+    fn lock(&self) -> ShardGuard<'_> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+            while self.locked.load(Ordering::Relaxed) {
+                hint::spin_loop();
+            }
+        }
+    }
+}
+
+impl Deref for ShardGuard<'_> {
+    type Target = LinkedListAllocator;
+    fn deref(&self) -> &Self::Target {
+        &self.0.heap
+    }
+}
+
+impl Drop for ShardGuard<'_> {
+    fn drop(&mut self) {
+        self.0.locked.store(false, Ordering::Release);
+    }
+}
+
+/// How a `ShardedAllocator` picks the shard a thread allocates from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Routing {
+    /// The shard of the CPU the thread is running on, the default
+    Cpu,
+    /// The shard of the thread, numbered in the order threads first allocate
+    /// Threads keep their shard wherever they're scheduled, which suits more threads than CPUs
+    Thread,
+}
+
+/// Next index handed to a thread allocating with `Routing::Thread`
+static NEXT_THREAD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static THREAD_INDEX: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+// Splits the heap into `SHARDS` linked list arenas, each behind its own lock
+// Allocations go to the shard of the CPU or thread, see `Routing`, so concurrent threads rarely contend
+// If that shard is busy the next free one is used instead
+// Blocks are freed back to whichever shard's arena they lie in, from any thread
+// Every arena reserves its own address range from the OS through `LibcMmap`, rather than sharing one source
+// An arena only grows by committing the pages right above its top, so shards carving pages from a common
+// reservation would run into each other, while separate reservations cost nothing until they're committed
+pub struct ShardedAllocator<const SHARDS: usize = 8> {
+    shards: [Shard; SHARDS],
+    routing: Routing,
+}
+
+// Each arena is only ever touched while its shard is locked
+unsafe impl<const SHARDS: usize> Sync for ShardedAllocator<SHARDS> {}
+unsafe impl<const SHARDS: usize> Send for ShardedAllocator<SHARDS> {}
+
+impl<const SHARDS: usize> ShardedAllocator<SHARDS> {
+    pub fn new() -> Self {
+        Self::with_routing(Routing::Cpu)
+    }
+
+    /// Like `new`, but reports a failure to set up any shard's arena instead of panicking
+    /// The arenas of the shards already set up are released
+    pub fn try_new() -> Result<Self, AllocFailure> {
+        Self::try_with_routing(Routing::Cpu)
+    }
+
+    /// Sets up the shards, routing allocations to them by `routing`
+    /// Panics if any shard's arena can't be set up, see `try_with_routing`
+    pub fn with_routing(routing: Routing) -> Self {
+        match Self::try_with_routing(routing) {
+            Ok(allocator) => allocator,
+            Err(failure) => panic!("Failed to set up the arena: {failure}"),
+        }
+    }
+
+    /// Like `with_routing`, but reports a failure to set up any shard's arena instead of panicking
+    pub fn try_with_routing(routing: Routing) -> Result<Self, AllocFailure> {
+        const { assert!(SHARDS > 0) };
+        let mut heaps: [Option<LinkedListAllocator>; SHARDS] = [const { None }; SHARDS];
+        let mut failure = None;
+        for slot in &mut heaps {
+            match LinkedListAllocator::try_new() {
+                Ok(heap) => *slot = Some(heap),
+                Err(err) => {
+                    failure = Some(err);
+                    break;
+                }
+            }
+        }
+        if let Some(failure) = failure {
+            heaps
+                .into_iter()
+                .flatten()
+                .for_each(LinkedListAllocator::free_allocator);
+            return Err(failure);
+        }
+
+        Ok(Self {
+            shards: heaps.map(|heap| Shard {
+                locked: AtomicBool::new(false),
+                heap: heap.unwrap(),
+            }),
+            routing,
+        })
+    }
+
+    /// Releases every shard's arena
+    pub fn free_allocator(self) {
+        for shard in self.shards {
+            shard.heap.free_allocator();
+        }
+    }
+
+    pub fn routing(&self) -> Routing {
+        self.routing
+    }
+
+    /// Index of the shard for the current CPU or thread
+    fn home_shard(&self) -> usize {
+        let index = match self.routing {
+            Routing::Cpu => usize::try_from(unsafe { libc::sched_getcpu() }).unwrap_or(0),
+            Routing::Thread => thread_index(),
+        };
+        index % SHARDS
+    }
+
+    /// Locks the home shard, or the first free one after it if it's taken
+    fn lock_any(&self) -> ShardGuard<'_> {
+        let home = self.home_shard();
+        (0..SHARDS)
+            .find_map(|i| self.shards[(home + i) % SHARDS].try_lock())
+            .unwrap_or_else(|| self.shards[home].lock())
+    }
+
+    /// Locks the shard whose arena holds `ptr`
+    fn lock_owner(&self, ptr: *mut u8) -> Option<ShardGuard<'_>> {
+        self.shards
+            .iter()
+            .find(|shard| shard.heap.owns(ptr))
+            .map(Shard::lock)
+    }
+}
+
+/// Index of the current thread, assigned the first time it's asked for
+/// Threads being torn down, whose locals are gone, all share index 0
+fn thread_index() -> usize {
+    THREAD_INDEX
+        .try_with(|index| match index.get() {
+            Some(index) => index,
+            None => {
+                let next = NEXT_THREAD.fetch_add(1, Ordering::Relaxed);
+                index.set(Some(next));
+                next
+            }
+        })
+        .unwrap_or(0)
+}
+
+impl<const SHARDS: usize> Default for ShardedAllocator<SHARDS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SHARDS: usize> fmt::Debug for ShardedAllocator<SHARDS> {
+    /// Each shard is locked while it's printed
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut list = f.debug_list();
+        for shard in &self.shards {
+            list.entry(&*shard.lock());
+        }
+        list.finish()
+    }
+}
+
+unsafe impl<const SHARDS: usize> GlobalAlloc for ShardedAllocator<SHARDS> {
+    #[track_caller]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.lock_any().alloc(layout) };
+        if !ptr.is_null() {
+            return ptr;
+        }
+
+        // The chosen shard is full, fall back to any other with room
+        self.shards
+            .iter()
+            .map(|shard| unsafe { shard.lock().alloc(layout) })
+            .find(|ptr| !ptr.is_null())
+            .unwrap_or(ptr::null_mut())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(shard) = self.lock_owner(ptr) {
+            unsafe { shard.dealloc(ptr, layout) };
+        }
+    }
+
+    /// Resizes the block within its own shard
+    #[track_caller]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        match self.lock_owner(ptr) {
+            Some(shard) => unsafe { shard.realloc(ptr, layout, new_size) },
+            None => ptr::null_mut(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::sync::atomic::AtomicPtr;
+    use std::thread;
+
+    use super::*;
+
+    // Each shard's arena is only touched while it's locked
+    const _: () = {
+        crate::assert_send::<ShardedAllocator>();
+        crate::assert_sync::<ShardedAllocator>();
+    };
+
+    #[test]
+    fn threads() {
+        let allocator = ShardedAllocator::<4>::new();
+        let layout = Layout::new::<[u64; 8]>();
+
+        thread::scope(|scope| {
+            for t in 0..8u64 {
+                let allocator = &allocator;
+                scope.spawn(move || unsafe {
+                    for round in 0..100 {
+                        let ptrs = (0..16).map(|_| allocator.alloc(layout)).collect::<Vec<_>>();
+                        for &ptr in &ptrs {
+                            assert!(!ptr.is_null());
+                            ptr.cast::<[u64; 8]>().write([t * 1000 + round; 8]);
+                        }
+                        for ptr in ptrs {
+                            assert_eq!(ptr.cast::<[u64; 8]>().read(), [t * 1000 + round; 8]);
+                            allocator.dealloc(ptr, layout);
+                        }
+                    }
+                });
+            }
+        });
+
+        // Everything was freed, so every shard is back to free blocks only
+        for shard in &allocator.shards {
+            let heap = shard.lock();
+            assert!(unsafe { !heap.alloc(layout).is_null() });
+        }
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn thread_routing() {
+        let allocator = ShardedAllocator::<2>::with_routing(Routing::Thread);
+        let layout = Layout::new::<[u8; 64]>();
+        let shard_of = |ptr: *mut u8| {
+            (0..2)
+                .find(|&i| allocator.shards[i].heap.owns(ptr))
+                .unwrap()
+        };
+
+        // One after the other, so they're numbered consecutively and never find their shard locked
+        let alloc_on_thread = || {
+            thread::scope(|scope| {
+                // Raw pointers aren't `Send`, an atomic one carries it back
+                let ptr = scope.spawn(|| AtomicPtr::new(unsafe { allocator.alloc(layout) }));
+                ptr.join().unwrap().into_inner()
+            })
+        };
+        let one = alloc_on_thread();
+        let two = alloc_on_thread();
+        assert_ne!(shard_of(one), shard_of(two));
+
+        // Freed from this thread, through whichever shard owns them
+        unsafe {
+            allocator.dealloc(one, layout);
+            allocator.dealloc(two, layout);
+        }
+        for shard in &allocator.shards {
+            assert_eq!(shard.heap.used_bytes(), 0);
+        }
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn cross_shard_free() {
+        let allocator = ShardedAllocator::<2>::new();
+        let layout = Layout::new::<[u8; 64]>();
+
+        unsafe {
+            // Allocated straight from the second shard, freed through the allocator
+            let ptr = allocator.shards[1].lock().alloc(layout);
+            assert!(allocator.shards[1].heap.owns(ptr));
+            assert!(!allocator.shards[0].heap.owns(ptr));
+
+            let grown = allocator.realloc(ptr, layout, 128);
+            assert!(allocator.shards[1].heap.owns(grown));
+            allocator.dealloc(grown, Layout::new::<[u8; 128]>());
+        }
+        allocator.free_allocator();
+    }
+}
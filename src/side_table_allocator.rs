@@ -0,0 +1,367 @@
+use core::{alloc::GlobalAlloc, cmp, fmt, ops::Range, ptr};
+
+use crate::{
+    error::AllocFailure,
+    page_source::{LibcMmap, PageSource},
+};
+
+const PAGE_SIZE: usize = 4096;
+/// Blocks are measured in granules, which is also the smallest alignment handed out
+const GRANULE: usize = 16;
+const ARENA_SIZE: usize = PAGE_SIZE * 256;
+const GRANULES: usize = ARENA_SIZE / GRANULE;
+const TABLE_SIZE: usize = GRANULES * size_of::<u32>();
+/// The arena is page aligned, so any alignment up to a page falls on a granule boundary
+const MAX_ALIGN: usize = PAGE_SIZE;
+
+const USED_BIT: u32 = 1 << 31;
+
+// Headers are kept out of band, in a table with an entry for every granule of the arena
+// Entry `i` describes the block starting at granule `i`, entries inside a block are unused
+// User data is contiguous, and aligning a block doesn't need room for a header in front of it
+// Finding a block from its pointer is a lookup rather than a walk
+// Trades a fixed table, a quarter of the arena's size, for the per-block headers of `LinkedListAllocator`
+pub struct SideTableAllocator<S: PageSource = LibcMmap> {
+    data: *mut u8,
+    table: *mut u32,
+    source: S,
+}
+
+impl SideTableAllocator {
+    /// Largest alignment `alloc` will satisfy, larger requests return null
+    pub const MAX_ALIGN: usize = MAX_ALIGN;
+
+    pub fn new() -> Self {
+        Self::with_source(LibcMmap)
+    }
+
+    /// Like `new`, but reports a failure to reserve or commit the arena instead of panicking
+    pub fn try_new() -> Result<Self, AllocFailure> {
+        Self::try_with_source(LibcMmap)
+    }
+}
+
+impl Default for SideTableAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: PageSource> fmt::Debug for SideTableAllocator<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SideTableAllocator")
+            .field("capacity", &ARENA_SIZE)
+            .field("used", &self.used_bytes())
+            .field("blocks", &self.number_of_blocks())
+            .finish()
+    }
+}
+
+impl<S: PageSource> SideTableAllocator<S> {
+    /// Reserves and backs the arena and its table from `source`
+    /// The table sits right after the arena, in the same reservation
+    /// Panics if either fails, see `try_with_source`
+    pub fn with_source(source: S) -> Self {
+        match Self::try_with_source(source) {
+            Ok(allocator) => allocator,
+            Err(failure) => panic!("Failed to set up the arena: {failure}"),
+        }
+    }
+
+    /// Reserves and backs the arena and its table from `source`, reporting a failure instead of panicking
+    pub fn try_with_source(source: S) -> Result<Self, AllocFailure> {
+        unsafe {
+            let data = source.reserve(ARENA_SIZE + TABLE_SIZE);
+            if data.is_null() {
+                return Err(AllocFailure::ReserveFailed);
+            }
+            if source.map(data, ARENA_SIZE + TABLE_SIZE).is_null() {
+                source.release(data, ARENA_SIZE + TABLE_SIZE);
+                return Err(AllocFailure::CommitFailed);
+            }
+
+            let table = data.add(ARENA_SIZE).cast::<u32>();
+            let allocator = Self {
+                data,
+                table,
+                source,
+            };
+            allocator.set_entry(0, GRANULES, false);
+
+            Ok(allocator)
+        }
+    }
+
+    /// Allocates zeroed memory for `count` elements of `size` bytes, like C's `calloc`
+    /// Returns null if the total size is zero or overflows
+    pub fn calloc(&self, count: usize, size: usize) -> *mut u8 {
+        match crate::calloc_layout(count, size) {
+            Some(layout) => unsafe { self.alloc_zeroed(layout) },
+            None => ptr::null_mut(),
+        }
+    }
+
+    /// The whole arena, every block this allocator hands out lies within it
+    pub fn as_ptr_range(&self) -> Range<*mut u8> {
+        self.data..unsafe { self.data.add(ARENA_SIZE) }
+    }
+
+    pub fn number_of_blocks(&self) -> usize {
+        let mut c = 0;
+        let mut i = 0;
+        while i < GRANULES {
+            c += 1;
+            i += self.entry(i).0;
+        }
+
+        c
+    }
+
+    /// Total size of the blocks in use, in whole granules
+    pub fn used_bytes(&self) -> usize {
+        let mut used = 0;
+        let mut i = 0;
+        while i < GRANULES {
+            let (len, in_use) = self.entry(i);
+            if in_use {
+                used += len * GRANULE;
+            }
+            i += len;
+        }
+
+        used
+    }
+
+    pub fn free_allocator(self) {
+        unsafe { self.source.release(self.data, ARENA_SIZE + TABLE_SIZE) };
+    }
+
+    /// Length in granules and whether it's used, of the block starting at granule `i`
+    fn entry(&self, i: usize) -> (usize, bool) {
+        let entry = unsafe { self.table.add(i).read() };
+        ((entry & !USED_BIT) as usize, entry & USED_BIT != 0)
+    }
+
+    fn set_entry(&self, i: usize, len: usize, used: bool) {
+        let used = if used { USED_BIT } else { 0 };
+        unsafe { self.table.add(i).write(len as u32 | used) };
+    }
+
+    /// Absorbs the free blocks following the one at granule `i` and returns its new length
+    fn merge_following(&self, i: usize) -> usize {
+        let (mut len, used) = self.entry(i);
+        while i + len < GRANULES {
+            let (next_len, next_used) = self.entry(i + len);
+            if next_used {
+                break;
+            }
+            self.set_entry(i + len, 0, false);
+            len += next_len;
+        }
+        self.set_entry(i, len, used);
+
+        len
+    }
+
+    /// Granule of the used block starting at `ptr`, None if `ptr` isn't one
+    fn block_index(&self, ptr: *mut u8) -> Option<usize> {
+        let offset = ptr.addr().checked_sub(self.data.addr())?;
+        if offset >= ARENA_SIZE || !offset.is_multiple_of(GRANULE) {
+            return None;
+        }
+
+        let i = offset / GRANULE;
+        self.entry(i).1.then_some(i)
+    }
+
+    /// Number of granules needed for `size` bytes, zero sized blocks still take one
+    fn granules(size: usize) -> Option<usize> {
+        Some(size.max(1).checked_next_multiple_of(GRANULE)? / GRANULE)
+    }
+}
+
+unsafe impl<S: PageSource> GlobalAlloc for SideTableAllocator<S> {
+    #[track_caller]
+    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
+        let align = crate::checked_align(layout.align());
+        if align > MAX_ALIGN {
+            return ptr::null_mut();
+        }
+        let Some(len) = Self::granules(layout.size()) else {
+            return ptr::null_mut();
+        };
+        let align = align.max(GRANULE) / GRANULE;
+
+        let mut i = 0;
+        while i < GRANULES {
+            let (block_len, used) = self.entry(i);
+            if used {
+                i += block_len;
+                continue;
+            }
+
+            let block_len = self.merge_following(i);
+            let start = i.next_multiple_of(align);
+            if start + len <= i + block_len {
+                // The padding in front of an aligned block stays free as a block of its own
+                if start > i {
+                    self.set_entry(i, start - i, false);
+                }
+                self.set_entry(start, len, true);
+                let leftover = i + block_len - start - len;
+                if leftover > 0 {
+                    self.set_entry(start + len, leftover, false);
+                }
+
+                return unsafe { self.data.add(start * GRANULE) };
+            }
+
+            i += block_len;
+        }
+
+        ptr::null_mut()
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: core::alloc::Layout) {
+        let Some(i) = self.block_index(ptr) else {
+            return;
+        };
+
+        let (len, _) = self.entry(i);
+        self.set_entry(i, len, false);
+    }
+
+    /// Resizes the block in place when it shrinks or the blocks after it are free, otherwise moves it
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: core::alloc::Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        let Some(i) = self.block_index(ptr) else {
+            return ptr::null_mut();
+        };
+        let Some(new_len) = Self::granules(new_size) else {
+            return ptr::null_mut();
+        };
+
+        let (len, _) = self.entry(i);
+        let available = if new_len > len && i + len < GRANULES && !self.entry(i + len).1 {
+            len + self.merge_following(i + len)
+        } else {
+            len
+        };
+        if new_len <= available {
+            self.set_entry(i, new_len, true);
+            if available > new_len {
+                self.set_entry(i + new_len, available - new_len, false);
+                self.merge_following(i + new_len);
+            }
+
+            return ptr;
+        }
+
+        let Some(new_layout) = crate::realloc_layout(layout, new_size) else {
+            return ptr::null_mut();
+        };
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe {
+                ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(layout.size(), new_size));
+                self.dealloc(ptr, layout);
+            }
+        }
+
+        new_ptr
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::alloc::Layout;
+
+    use super::*;
+
+    // The arena and its table sit behind raw pointers and nothing synchronises the table
+    crate::assert_not_impl!(SideTableAllocator, Send);
+    crate::assert_not_impl!(SideTableAllocator, Sync);
+
+    #[test]
+    fn alloc_chunks() {
+        let allocator = SideTableAllocator::new();
+        let layout = Layout::new::<[u8; 16]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            let two = allocator.alloc(layout);
+            // No header between them
+            assert_eq!(two, one.add(16));
+            assert_eq!(allocator.number_of_blocks(), 3);
+
+            allocator.dealloc(one, layout);
+            assert_eq!(allocator.alloc(layout), one);
+
+            let too_large = allocator.alloc(Layout::from_size_align(ARENA_SIZE, 1).unwrap());
+            assert!(too_large.is_null());
+        }
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn alignment() {
+        let allocator = SideTableAllocator::new();
+
+        unsafe {
+            let _ = allocator.alloc(Layout::new::<u8>());
+            for align in [1, 16, 64, PAGE_SIZE] {
+                let ptr = allocator.alloc(Layout::from_size_align(24, align).unwrap());
+                assert!(ptr.addr().is_multiple_of(align));
+                assert!(allocator.as_ptr_range().contains(&ptr));
+            }
+            let too_aligned = Layout::from_size_align(8, PAGE_SIZE * 2).unwrap();
+            assert!(allocator.alloc(too_aligned).is_null());
+        }
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn realloc() {
+        let allocator = SideTableAllocator::new();
+        let layout = Layout::new::<[u8; 32]>();
+
+        unsafe {
+            let one = allocator.alloc(layout);
+            (0..32).for_each(|i| one.add(i).write(i as u8));
+            let two = allocator.alloc(layout);
+
+            // Boxed in by `two`, so it has to move
+            let moved = allocator.realloc(one, layout, 64);
+            assert_ne!(moved, one);
+            (0..32).for_each(|i| assert_eq!(moved.add(i).read(), i as u8));
+
+            // Only free space follows the moved block, so it grows in place, then shrinks back
+            let moved_layout = Layout::new::<[u8; 64]>();
+            assert_eq!(allocator.realloc(moved, moved_layout, 96), moved);
+            assert_eq!(
+                allocator.realloc(moved, Layout::new::<[u8; 96]>(), 16),
+                moved
+            );
+            (0..16).for_each(|i| assert_eq!(moved.add(i).read(), i as u8));
+            // The first block's space, `two`, the moved block and the rest of the arena
+            assert_eq!(allocator.number_of_blocks(), 4);
+            assert!(allocator.as_ptr_range().contains(&two));
+        }
+        allocator.free_allocator();
+    }
+
+    #[test]
+    fn calloc() {
+        let allocator = SideTableAllocator::new();
+
+        assert!(allocator.calloc(usize::MAX, 2).is_null());
+        let ptr = allocator.calloc(500, 8);
+        assert!(!ptr.is_null());
+        unsafe { assert!((0..4000).all(|i| ptr.add(i).read() == 0)) };
+        allocator.free_allocator();
+    }
+}
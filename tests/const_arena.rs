@@ -0,0 +1,18 @@
+//! A minimal program running entirely on a static arena, without the test harness allocating alongside it
+
+use yerba::const_arena::ConstArena;
+
+#[global_allocator]
+static ARENA: ConstArena<1024> = ConstArena::new();
+
+fn main() {
+    let before = ARENA.used();
+
+    let numbers = (0..16u32).collect::<Vec<_>>();
+    let text = format!("{}", numbers.iter().sum::<u32>());
+    assert_eq!(text, "120");
+
+    let range = ARENA.as_ptr_range();
+    assert!(range.contains(&numbers.as_ptr().cast_mut().cast()));
+    assert!(ARENA.used() > before);
+}
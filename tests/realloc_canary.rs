@@ -0,0 +1,133 @@
+use core::alloc::{GlobalAlloc, Layout};
+
+use yerba::{
+    const_arena::ConstArena,
+    histogram::Histogram,
+    linear_allocator::LinearAllocator,
+    linked_list_allocator::LinkedListAllocator,
+    page_allocator::YerbaAlloc,
+    sharded_allocator::ShardedAllocator,
+    side_table_allocator::SideTableAllocator,
+    stack_allocator::{GrowableStack, StackAllocator},
+};
+
+/// Sizes that stay within the fixed buffers of the stack and linear allocators
+const SMALL_SIZES: [usize; 7] = [16, 64, 8, 200, 1000, 24, 24];
+/// Sizes that cross page boundaries in both directions
+const PAGE_SIZES: [usize; 6] = [16, 5000, 100, 20000, 4096 * 3, 4096 * 3 + 1];
+
+fn canary(i: usize) -> u8 {
+    i as u8
+}
+
+unsafe fn fill(ptr: *mut u8, size: usize) {
+    (0..size).for_each(|i| unsafe { ptr.add(i).write(canary(i)) });
+}
+
+/// Allocates a block of `sizes[0]` bytes filled with a position-dependent canary,
+/// then reallocs it through every following size, checking after each step that
+/// the prefix that should have survived still holds the canary
+unsafe fn check_realloc<A: GlobalAlloc>(allocator: &A, sizes: &[usize]) {
+    let mut layout = Layout::from_size_align(sizes[0], 8).unwrap();
+
+    unsafe {
+        let mut ptr = allocator.alloc(layout);
+        assert!(!ptr.is_null());
+        fill(ptr, layout.size());
+
+        for &new_size in &sizes[1..] {
+            let new_ptr = allocator.realloc(ptr, layout, new_size);
+            assert!(
+                !new_ptr.is_null(),
+                "realloc from {} to {new_size} failed",
+                layout.size()
+            );
+            assert!(new_ptr.addr().is_multiple_of(layout.align()));
+
+            let kept = layout.size().min(new_size);
+            for i in 0..kept {
+                assert_eq!(
+                    new_ptr.add(i).read(),
+                    canary(i),
+                    "byte {i} lost reallocating from {} to {new_size}",
+                    layout.size()
+                );
+            }
+
+            layout = Layout::from_size_align(new_size, layout.align()).unwrap();
+            ptr = new_ptr;
+            fill(ptr, layout.size());
+        }
+
+        allocator.dealloc(ptr, layout);
+    }
+}
+
+/// Reallocs a canary filled block to sizes so large that computing the new block's end overflows
+/// Each must fail with null, leaving the block where it was and intact
+unsafe fn check_overflow<A: GlobalAlloc>(allocator: &A) {
+    let layout = Layout::from_size_align(64, 8).unwrap();
+
+    unsafe {
+        let ptr = allocator.alloc(layout);
+        assert!(!ptr.is_null());
+        fill(ptr, layout.size());
+
+        for new_size in [usize::MAX, usize::MAX - 64, isize::MAX as usize] {
+            let new_ptr = allocator.realloc(ptr, layout, new_size);
+            assert!(new_ptr.is_null(), "realloc to {new_size} didn't fail");
+            for i in 0..layout.size() {
+                assert_eq!(ptr.add(i).read(), canary(i), "byte {i} lost to {new_size}");
+            }
+        }
+
+        allocator.dealloc(ptr, layout);
+    }
+}
+
+#[test]
+fn overflow() {
+    unsafe {
+        check_overflow(&StackAllocator::new());
+        check_overflow(&GrowableStack::new());
+        check_overflow(&LinearAllocator::new());
+        check_overflow(&YerbaAlloc::new());
+        check_overflow(&ConstArena::<1024>::new());
+        check_overflow(&Histogram::new(YerbaAlloc::new()));
+
+        let linked_list = LinkedListAllocator::new();
+        check_overflow(&linked_list);
+        linked_list.free_allocator();
+        let side_table = SideTableAllocator::new();
+        check_overflow(&side_table);
+        side_table.free_allocator();
+        let sharded = ShardedAllocator::<2>::new();
+        check_overflow(&sharded);
+        sharded.free_allocator();
+    }
+}
+
+#[test]
+fn stack_allocator() {
+    let allocator = StackAllocator::new();
+    unsafe { check_realloc(&allocator, &SMALL_SIZES) };
+}
+
+#[test]
+fn linear_allocator() {
+    let allocator = LinearAllocator::new();
+    unsafe { check_realloc(&allocator, &SMALL_SIZES) };
+}
+
+#[test]
+fn linked_list_allocator() {
+    let allocator = LinkedListAllocator::new();
+    unsafe { check_realloc(&allocator, &SMALL_SIZES) };
+    allocator.free_allocator();
+}
+
+#[test]
+fn page_allocator() {
+    let allocator = YerbaAlloc::new();
+    unsafe { check_realloc(&allocator, &PAGE_SIZES) };
+}
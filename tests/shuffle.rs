@@ -0,0 +1,160 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ops::Range;
+
+use yerba::linked_list_allocator::LinkedListAllocator;
+
+/// Fixed so a failure can be replayed, change it to explore other sequences
+const SEED: u64 = 0x5eed_f00d_7e57;
+const OPERATIONS: usize = 20_000;
+
+/// xorshift64*, enough to shuffle the operations without pulling in a dependency
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// Mostly small sizes, with the occasional block spanning pages
+    fn size(&mut self) -> usize {
+        match self.below(16) {
+            0 => 1 + self.below(4096 * 3),
+            1..4 => 1 + self.below(1024),
+            _ => 1 + self.below(128),
+        }
+    }
+
+    fn layout(&mut self) -> Layout {
+        let align = 1 << self.below(LinkedListAllocator::MAX_ALIGN.trailing_zeros() as usize + 1);
+        Layout::from_size_align(self.size(), align).unwrap()
+    }
+}
+
+struct Live {
+    ptr: *mut u8,
+    layout: Layout,
+    /// Every byte of the block holds this, so writes through another block show up
+    tag: u8,
+}
+
+impl Live {
+    fn range(&self) -> Range<usize> {
+        self.ptr.addr()..self.ptr.addr() + self.layout.size()
+    }
+}
+
+/// Checks that `ptr` is aligned, inside the committed arena and clear of every other live block
+#[track_caller]
+fn check_placement(
+    arena: &Range<*mut u8>,
+    live: &[Live],
+    ptr: *mut u8,
+    layout: Layout,
+    step: usize,
+) {
+    let range = ptr.addr()..ptr.addr() + layout.size();
+    assert!(
+        ptr.addr().is_multiple_of(layout.align()),
+        "step {step}: {ptr:p} isn't aligned for {layout:?}"
+    );
+    assert!(
+        arena.start.addr() <= range.start && range.end <= arena.end.addr(),
+        "step {step}: {range:x?} lies outside the arena {arena:?}"
+    );
+    for other in live {
+        let other_range = other.range();
+        assert!(
+            range.end <= other_range.start || other_range.end <= range.start,
+            "step {step}: {range:x?} overlaps the live block {other_range:x?}"
+        );
+    }
+}
+
+#[track_caller]
+unsafe fn check_tag(block: &Live, len: usize, step: usize) {
+    for i in 0..len {
+        let byte = unsafe { block.ptr.add(i).read() };
+        assert_eq!(
+            byte, block.tag,
+            "step {step}: byte {i} of the block at {:p} was overwritten",
+            block.ptr
+        );
+    }
+}
+
+#[test]
+fn shuffle() {
+    let allocator = LinkedListAllocator::new();
+    let mut rng = Rng(SEED);
+    let mut live: Vec<Live> = Vec::new();
+
+    for step in 0..OPERATIONS {
+        if let Err(broken) = allocator.validate() {
+            panic!("step {step}: {broken}");
+        }
+
+        match rng.below(3) {
+            // Allocate more often than free, so the arena fills up and fragments
+            0 | 1 if live.len() < 256 => {
+                let layout = rng.layout();
+                let ptr = unsafe { allocator.alloc(layout) };
+                if ptr.is_null() {
+                    continue;
+                }
+                check_placement(&allocator.as_ptr_range(), &live, ptr, layout, step);
+
+                let tag = step as u8;
+                unsafe { ptr.write_bytes(tag, layout.size()) };
+                live.push(Live { ptr, layout, tag });
+            }
+            0 | 1 if !live.is_empty() => {
+                let block = live.swap_remove(rng.below(live.len()));
+                unsafe {
+                    check_tag(&block, block.layout.size(), step);
+                    allocator.dealloc(block.ptr, block.layout);
+                }
+            }
+            _ if !live.is_empty() => {
+                let block = live.swap_remove(rng.below(live.len()));
+                let new_size = rng.size();
+                let new_ptr = unsafe { allocator.realloc(block.ptr, block.layout, new_size) };
+                if new_ptr.is_null() {
+                    // A failed realloc leaves the block as it was
+                    unsafe { check_tag(&block, block.layout.size(), step) };
+                    live.push(block);
+                    continue;
+                }
+
+                let new_layout = Layout::from_size_align(new_size, block.layout.align()).unwrap();
+                check_placement(&allocator.as_ptr_range(), &live, new_ptr, new_layout, step);
+                let moved = Live {
+                    ptr: new_ptr,
+                    layout: new_layout,
+                    tag: block.tag,
+                };
+                unsafe {
+                    check_tag(&moved, block.layout.size().min(new_size), step);
+                    new_ptr.write_bytes(moved.tag, new_size);
+                }
+                live.push(moved);
+            }
+            _ => {}
+        }
+    }
+
+    for block in live.drain(..) {
+        unsafe {
+            check_tag(&block, block.layout.size(), OPERATIONS);
+            allocator.dealloc(block.ptr, block.layout);
+        }
+    }
+    assert_eq!(allocator.used_bytes(), 0);
+    allocator.free_allocator();
+}
@@ -0,0 +1,47 @@
+use core::alloc::{GlobalAlloc, Layout};
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+};
+
+use yerba::stack_allocator::StackAllocator;
+
+/// Runs `f`, expecting it to panic, and returns the line the panic was reported at
+fn panic_line(f: impl FnOnce()) -> Option<u32> {
+    let location = Arc::new(Mutex::new(None));
+    let hook_location = location.clone();
+    panic::set_hook(Box::new(move |info| {
+        *hook_location.lock().unwrap() = info
+            .location()
+            .filter(|location| location.file() == file!())
+            .map(|location| location.line());
+    }));
+
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    let _ = panic::take_hook();
+    assert!(result.is_err());
+
+    location.lock().unwrap().take()
+}
+
+#[test]
+fn stack_misuse_reports_caller() {
+    let allocator = StackAllocator::new();
+    let layout = Layout::new::<[u8; 16]>();
+
+    unsafe {
+        let one = allocator.alloc(layout);
+        let two = allocator.alloc(layout);
+
+        let expected = line!() + 1;
+        let line = panic_line(|| allocator.dealloc(one, layout));
+        assert_eq!(line, Some(expected));
+
+        let expected = line!() + 1;
+        let line = panic_line(|| allocator.free_tail(one, layout.size(), 8));
+        assert_eq!(line, Some(expected));
+
+        allocator.dealloc(two, layout);
+        allocator.dealloc(one, layout);
+    }
+}